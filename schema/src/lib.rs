@@ -0,0 +1,7 @@
+// SPDX-License-Identifier: MIT
+
+//! The format of the configuration files, shared by the library and the `vgpu_unlock-upgrade`
+//! tool so that the tool does not link the `ioctl` hook.
+
+pub mod migrate;
+pub mod schema;
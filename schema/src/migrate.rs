@@ -0,0 +1,152 @@
+// SPDX-License-Identifier: MIT
+
+//! Versioning of the configuration file format and migration of files written for older
+//! versions.
+//!
+//! Every file may declare the version of the format it is written for with a top-level
+//! `version = N` key. Files without one are version 1. Keys renamed since the version a file
+//! declares are still accepted: they are reported as deprecated and migrated to their current
+//! name when the file is loaded. The `vgpu_unlock-upgrade` tool rewrites a file to the current
+//! version while keeping its comments and formatting.
+//!
+//! Version history:
+//!
+//! 1. The original format.
+
+use std::fmt;
+
+use toml::{Table, Value};
+
+use crate::schema::Schema;
+
+/// The version of the format this build writes and understands.
+pub const CURRENT_VERSION: i64 = 1;
+/// The top-level key holding the version of a file.
+pub const VERSION_KEY: &str = "version";
+
+/// A key that was renamed in version `since` of the format.
+pub struct Rename {
+    pub since: i64,
+    pub from: &'static str,
+    pub to: &'static str,
+}
+
+impl Rename {
+    /// Whether a file written for `version` still uses the old name.
+    #[inline]
+    pub fn applies_to(&self, version: i64) -> bool {
+        version < self.since
+    }
+}
+
+/// A version that is not understood by this build.
+#[derive(Debug)]
+pub struct VersionError(pub String);
+
+impl fmt::Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "unsupported version {}, expected an integer from 1 to {}",
+            self.0, CURRENT_VERSION
+        )
+    }
+}
+
+/// Returns the version `table` declares.
+pub fn version(table: &Table) -> Result<i64, VersionError> {
+    match table.get(VERSION_KEY) {
+        None => Ok(1),
+        Some(Value::Integer(version)) if (1..=CURRENT_VERSION).contains(version) => Ok(*version),
+        Some(value) => Err(VersionError(value.to_string())),
+    }
+}
+
+/// Renames the keys of `table`, written for `version`, to their current names. When both the
+/// old and the current name are set, the current name wins.
+pub fn migrate(table: &mut Table, schema: &Schema, version: i64) {
+    match schema {
+        Schema::Struct(_, nested) => {
+            for (name, schema) in nested.iter() {
+                if let Some(value) = table.get_mut(*name) {
+                    migrate_value(value, schema, version);
+                }
+            }
+        }
+        Schema::Map(schema) | Schema::Keyed(_, schema) => {
+            for (_, value) in table.iter_mut() {
+                migrate_value(value, schema, version);
+            }
+        }
+        Schema::Array(_) => {}
+        Schema::Renamed(renames, schema) => {
+            for rename in renames.iter().filter(|rename| rename.applies_to(version)) {
+                if let Some(value) = table.remove(rename.from) {
+                    table.entry(rename.to).or_insert(value);
+                }
+            }
+
+            migrate(table, schema, version);
+        }
+        Schema::Restricted(_, _, schema) => migrate(table, schema, version),
+    }
+}
+
+fn migrate_value(value: &mut Value, schema: &Schema, version: i64) {
+    match (value, schema) {
+        (Value::Array(array), Schema::Array(schema)) => {
+            for value in array {
+                if let Value::Table(table) = value {
+                    migrate(table, schema, version);
+                }
+            }
+        }
+        (Value::Table(table), schema) => migrate(table, schema, version),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use toml::Table;
+
+    use super::{migrate, version, Rename};
+    use crate::schema::Schema;
+
+    // No key has been renamed yet, so the tests use a version 2 of their own.
+    static RENAMES: &[Rename] = &[Rename {
+        since: 2,
+        from: "gpu_type",
+        to: "vgpu_type_id",
+    }];
+    static ENTRY_FIELDS: Schema = Schema::Struct(&[], &[]);
+    static ROOT: Schema = Schema::Struct(&["profile"], &[("profile", Schema::Map(&PROFILE))]);
+    static PROFILE: Schema = Schema::Renamed(RENAMES, &ENTRY_FIELDS);
+
+    #[test]
+    fn test_version() {
+        let table = |data: &str| toml::from_str::<Table>(data).unwrap();
+
+        assert_eq!(version(&table("")).unwrap(), 1);
+        assert_eq!(version(&table("version = 1")).unwrap(), 1);
+        assert!(version(&table("version = 2")).is_err());
+        assert!(version(&table("version = '1'")).is_err());
+    }
+
+    #[test]
+    fn test_migrate() {
+        let data = "[profile.nvidia-55]\ngpu_type = 1\n[profile.nvidia-56]\ngpu_type = 1\n\
+                    vgpu_type_id = 2\n";
+
+        let mut table: Table = toml::from_str(data).unwrap();
+        migrate(&mut table, &ROOT, 1);
+        assert_eq!(
+            table.to_string(),
+            "[profile.nvidia-55]\nvgpu_type_id = 1\n\n[profile.nvidia-56]\nvgpu_type_id = 2\n"
+        );
+
+        let mut table: Table = toml::from_str(data).unwrap();
+        migrate(&mut table, &ROOT, 2);
+        assert_eq!(table, toml::from_str(data).unwrap());
+    }
+}
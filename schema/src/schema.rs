@@ -0,0 +1,253 @@
+// SPDX-License-Identifier: MIT
+
+//! The keys allowed in `config.toml` and `profile_override.toml`.
+//!
+//! The schemas are plain data and only depend on [`crate::migrate`]. The field lists follow the
+//! structs the library decodes the files into, which is checked by the tests of its `config` and
+//! `overrides` modules.
+
+use crate::migrate::Rename;
+
+/// Describes the keys allowed in a part of a configuration file.
+pub enum Schema {
+    /// A table holding the fields of a struct. Fields that hold nested tables are listed with
+    /// their own schema, along with any tables that are allowed without being a field of the
+    /// struct.
+    Struct(&'static [&'static str], &'static [(&'static str, Schema)]),
+    /// A table with arbitrary keys whose values all follow the schema.
+    Map(&'static Schema),
+    /// A table whose keys all have the format of [`Keys`], and whose values all follow the
+    /// schema.
+    Keyed(Keys, &'static Schema),
+    /// An array of tables that all follow the schema.
+    Array(&'static Schema),
+    /// A table following the schema whose keys had other names in older versions of the format.
+    Renamed(&'static [Rename], &'static Schema),
+    /// A table following the schema, except for the listed keys which are not allowed there for
+    /// the reason given.
+    Restricted(&'static [&'static str], &'static str, &'static Schema),
+}
+
+/// The format of the keys of a [`Schema::Keyed`] table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Keys {
+    /// A vGPU type such as `nvidia-55`.
+    VgpuType,
+    /// A PCI device ID, which is a number in any case.
+    DeviceId,
+    /// The PCI address of a physical GPU.
+    PciAddress,
+    /// A Proxmox VMID or an inclusive range of VMIDs.
+    Vmids,
+}
+
+impl Keys {
+    /// Describes the format for the messages about keys that do not have it.
+    pub fn expected(self) -> &'static str {
+        match self {
+            Keys::VgpuType => "a vGPU type such as `nvidia-55`, or a `[profile_name]` table",
+            Keys::DeviceId => "a PCI device ID such as `0x1e84`",
+            Keys::PciAddress => "a PCI address such as `0000:41:00.0`",
+            Keys::Vmids => "a VMID or a range of VMIDs such as `1000-1999`",
+        }
+    }
+}
+
+/// Name of the table holding the sections that only apply to a specific process.
+pub const PROCESS_TABLE: &str = "process";
+
+/// Keys allowed in `config.toml`.
+pub static CONFIG: Schema = Schema::Struct(
+    CONFIG_FIELDS,
+    &[
+        (
+            "pci_info_map",
+            Schema::Keyed(Keys::DeviceId, &PCI_INFO_MAP_ENTRY),
+        ),
+        ("features", FEATURES),
+        (PROCESS_TABLE, Schema::Map(&CONFIG_PROCESS_SECTION)),
+    ],
+);
+/// Keys allowed in a `[process.<name>]` section, everything but nested process sections.
+static CONFIG_PROCESS_SECTION: Schema = Schema::Struct(
+    CONFIG_FIELDS,
+    &[
+        (
+            "pci_info_map",
+            Schema::Keyed(Keys::DeviceId, &PCI_INFO_MAP_ENTRY),
+        ),
+        ("features", FEATURES),
+    ],
+);
+const FEATURES: Schema = Schema::Struct(FEATURES_FIELDS, &[]);
+static PCI_INFO_MAP_ENTRY: Schema = Schema::Struct(PCI_INFO_MAP_ENTRY_FIELDS, &[]);
+
+pub const CONFIG_FIELDS: &[&str] = &["unlock", "unlock_migration", "pci_info_map", "features"];
+pub const FEATURES_FIELDS: &[&str] = &[
+    "spoof_pci_info",
+    "spoof_virtualization_mode",
+    "ignore_a0820104_failure",
+    "ignore_zbc_clear_table_failure",
+    "inforom_workaround",
+];
+pub const PCI_INFO_MAP_ENTRY_FIELDS: &[&str] = &["device_id", "sub_system_id"];
+
+/// Keys allowed in `profile_override.toml`.
+pub static PROFILE_OVERRIDE: Schema = Schema::Struct(
+    PROFILE_OVERRIDES_FIELDS,
+    &[
+        (
+            "profile",
+            Schema::Keyed(Keys::VgpuType, &VGPU_PROFILE_OVERRIDE),
+        ),
+        ("profile_name", Schema::Map(&VGPU_PROFILE_OVERRIDE)),
+        ("gpu", Schema::Keyed(Keys::PciAddress, &GPU_OVERRIDE)),
+        ("group", Schema::Map(&GROUP)),
+        ("mdev", Schema::Map(&VGPU_PROFILE_OVERRIDE)),
+        ("vm", Schema::Keyed(Keys::Vmids, &VGPU_PROFILE_OVERRIDE)),
+        ("rule", Schema::Array(&RULE)),
+        (
+            PROCESS_TABLE,
+            Schema::Map(&PROFILE_OVERRIDE_PROCESS_SECTION),
+        ),
+    ],
+);
+/// Keys allowed in a `[process.<name>]` section of `profile_override.toml`.
+static PROFILE_OVERRIDE_PROCESS_SECTION: Schema = Schema::Struct(
+    PROFILE_OVERRIDES_FIELDS,
+    &[
+        (
+            "profile",
+            Schema::Keyed(Keys::VgpuType, &VGPU_PROFILE_OVERRIDE),
+        ),
+        ("profile_name", Schema::Map(&VGPU_PROFILE_OVERRIDE)),
+        ("gpu", Schema::Keyed(Keys::PciAddress, &GPU_OVERRIDE)),
+        ("group", Schema::Map(&GROUP)),
+        ("mdev", Schema::Map(&VGPU_PROFILE_OVERRIDE)),
+        ("vm", Schema::Keyed(Keys::Vmids, &VGPU_PROFILE_OVERRIDE)),
+        ("rule", Schema::Array(&RULE)),
+    ],
+);
+static GROUP: Schema = Schema::Struct(GROUP_FIELDS, &[("override", VGPU_PROFILE_OVERRIDE)]);
+static RULE: Schema = Schema::Struct(
+    RULE_FIELDS,
+    &[
+        ("match", Schema::Struct(CRITERIA_FIELDS, &[])),
+        ("override", VGPU_PROFILE_OVERRIDE),
+    ],
+);
+/// The overrides of a physical GPU, which can not change what `nvidia-vgpud` reports.
+static GPU_OVERRIDE: Schema = Schema::Restricted(
+    VGPUD_REPORTED_FIELDS,
+    "nvidia-vgpud does not know the physical GPU and reports the profile without it",
+    &VGPU_PROFILE_OVERRIDE,
+);
+const VGPU_PROFILE_OVERRIDE: Schema = Schema::Renamed(
+    VGPU_PROFILE_OVERRIDE_RENAMES,
+    &VGPU_PROFILE_OVERRIDE_CURRENT,
+);
+static VGPU_PROFILE_OVERRIDE_CURRENT: Schema = Schema::Struct(VGPU_PROFILE_OVERRIDE_FIELDS, &[]);
+/// Override keys renamed since version 1 of the format, none so far.
+static VGPU_PROFILE_OVERRIDE_RENAMES: &[Rename] = &[];
+
+#[cfg(feature = "proxmox")]
+pub const PROFILE_OVERRIDES_FIELDS: &[&str] = &[
+    "dry_run",
+    "profile",
+    "profile_name",
+    "gpu",
+    "group",
+    "mdev",
+    "vm",
+    "rule",
+];
+#[cfg(not(feature = "proxmox"))]
+pub const PROFILE_OVERRIDES_FIELDS: &[&str] = &[
+    "dry_run",
+    "profile",
+    "profile_name",
+    "gpu",
+    "group",
+    "mdev",
+    "rule",
+];
+#[cfg(feature = "proxmox")]
+pub const GROUP_FIELDS: &[&str] = &["vmids", "mdevs", "override"];
+#[cfg(not(feature = "proxmox"))]
+pub const GROUP_FIELDS: &[&str] = &["mdevs", "override"];
+pub const RULE_FIELDS: &[&str] = &["match", "override", "stop", "dry_run"];
+#[cfg(feature = "proxmox")]
+pub const CRITERIA_FIELDS: &[&str] = &[
+    "vgpu_type_id",
+    "vgpu_name",
+    "vgpu_class",
+    "mdev",
+    "vmid",
+    "gpu_bdf",
+    "device_id",
+];
+#[cfg(not(feature = "proxmox"))]
+pub const CRITERIA_FIELDS: &[&str] = &[
+    "vgpu_type_id",
+    "vgpu_name",
+    "vgpu_class",
+    "mdev",
+    "gpu_bdf",
+    "device_id",
+];
+pub const VGPU_PROFILE_OVERRIDE_FIELDS: &[&str] = &[
+    "gpu_type",
+    "card_name",
+    "vgpu_type",
+    "features",
+    "max_instances",
+    "num_displays",
+    "display_width",
+    "display_height",
+    "resolution",
+    "max_pixels",
+    "frl_config",
+    "cuda_enabled",
+    "ecc_supported",
+    "mig_instance_size",
+    "multi_vgpu_supported",
+    "pci_id",
+    "pci_device_id",
+    "profile_size",
+    "framebuffer",
+    "gsp_heap_size",
+    "mappable_video_size",
+    "framebuffer_reservation",
+    "encoder_capacity",
+    "bar1_length",
+    "frl_enabled",
+    "adapter_name",
+    "adapter_name_unicode",
+    "short_gpu_name",
+    "license_type",
+    "extra_params",
+    "ftrace_enable",
+    "gpu_direct_supported",
+    "nvlink_p2p_supported",
+    "multi_vgpu_exclusive",
+    "exclusive_type",
+    "exclusive_size",
+    "gpu_instance_profile_id",
+    "max_instance_per_gi",
+    "placement_size",
+    "homogeneous_placement_ids",
+    "heterogeneous_placement_ids",
+    "regenerate_placements",
+];
+/// Override keys of the fields `nvidia-vgpud` reports to the kernel for the mdev types of a
+/// physical GPU, which have to be the same in both daemons.
+pub const VGPUD_REPORTED_FIELDS: &[&str] = &[
+    "card_name",
+    "max_instances",
+    "num_displays",
+    "display_width",
+    "display_height",
+    "resolution",
+    "frl_config",
+    "framebuffer",
+];
@@ -0,0 +1,250 @@
+// SPDX-License-Identifier: MIT
+
+//! Prints a configuration file rewritten for the current version of the format, keeping its
+//! comments and formatting.
+//!
+//! Usage: `vgpu_unlock-upgrade <config|profile-override> <file>`
+//!
+//! The tool only links the schemas and the migration from the `vgpu_unlock-schema` crate, not the
+//! library, so running it does not load the configuration or install the `ioctl` hook.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::process;
+
+use toml_edit::{DocumentMut, Item, Key, TableLike, TomlError};
+use vgpu_unlock_schema::migrate;
+use vgpu_unlock_schema::schema::{self, Schema};
+
+const USAGE: &str = "Usage: vgpu_unlock-upgrade <config|profile-override> <file>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let (file, path) = match args.as_slice() {
+        [file, path] => (file.as_str(), path),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let schema = match file {
+        "config" => &schema::CONFIG,
+        "profile-override" => &schema::PROFILE_OVERRIDE,
+        _ => {
+            eprintln!("Unknown file kind '{}'\n{}", file, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let upgraded = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| upgrade(&data, schema).map_err(|e| e.to_string()));
+
+    match upgraded {
+        Ok(data) => print!("{}", data),
+        Err(e) => {
+            eprintln!("Failed to upgrade '{}': {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Why a file could not be upgraded.
+enum UpgradeError {
+    Parse(TomlError),
+    Version(migrate::VersionError),
+}
+
+impl fmt::Display for UpgradeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpgradeError::Parse(e) => fmt::Display::fmt(e, f),
+            UpgradeError::Version(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+/// Returns the TOML document `data` rewritten for the current version, keeping its comments and
+/// formatting.
+fn upgrade(data: &str, schema: &Schema) -> Result<String, UpgradeError> {
+    upgrade_to(data, schema, migrate::CURRENT_VERSION)
+}
+
+/// Returns the TOML document `data` rewritten for version `target`.
+fn upgrade_to(data: &str, schema: &Schema, target: i64) -> Result<String, UpgradeError> {
+    let mut document: DocumentMut = data.parse().map_err(UpgradeError::Parse)?;
+
+    let version = match document.get(migrate::VERSION_KEY) {
+        None => 1,
+        Some(item) => match item.as_integer() {
+            Some(version) if (1..=target).contains(&version) => version,
+            _ => {
+                let value = item.to_string();
+
+                return Err(UpgradeError::Version(migrate::VersionError(
+                    value.trim().to_string(),
+                )));
+            }
+        },
+    };
+
+    upgrade_table(document.as_table_mut(), schema, version);
+
+    match document
+        .get_mut(migrate::VERSION_KEY)
+        .and_then(Item::as_value_mut)
+    {
+        Some(value) => {
+            let decor = value.decor().clone();
+
+            *value = target.into();
+            *value.decor_mut() = decor;
+        }
+        None => {
+            document.insert(migrate::VERSION_KEY, toml_edit::value(target));
+        }
+    }
+
+    Ok(document.to_string())
+}
+
+fn upgrade_table(table: &mut dyn TableLike, schema: &Schema, version: i64) {
+    match schema {
+        Schema::Struct(_, nested) => {
+            for (name, schema) in nested.iter() {
+                if let Some(item) = table.get_mut(name) {
+                    upgrade_item(item, schema, version);
+                }
+            }
+        }
+        Schema::Map(schema) | Schema::Keyed(_, schema) => {
+            for (_, item) in table.iter_mut() {
+                upgrade_item(item, schema, version);
+            }
+        }
+        Schema::Array(_) => {}
+        Schema::Renamed(renames, schema) => {
+            let names: Vec<String> = table.iter().map(|(name, _)| name.to_string()).collect();
+            let renamed = |name: &str| {
+                renames
+                    .iter()
+                    .find(|rename| rename.from == name && rename.applies_to(version))
+            };
+
+            if names.iter().any(|name| renamed(name).is_some()) {
+                // Every key is removed and inserted again to keep them in their original order.
+                for name in &names {
+                    let key = table.key(name).cloned();
+                    let item = table.remove(name);
+                    let (key, item) = match (key, item) {
+                        (Some(key), Some(item)) => (key, item),
+                        _ => continue,
+                    };
+
+                    let key = match renamed(name) {
+                        // The current name takes precedence over the old one.
+                        Some(rename) if names.iter().any(|name| name == rename.to) => continue,
+                        Some(rename) => Key::new(rename.to)
+                            .with_leaf_decor(key.leaf_decor().clone())
+                            .with_dotted_decor(key.dotted_decor().clone()),
+                        None => key,
+                    };
+
+                    table.entry_format(&key).or_insert(item);
+                }
+            }
+
+            upgrade_table(table, schema, version);
+        }
+        Schema::Restricted(_, _, schema) => upgrade_table(table, schema, version),
+    }
+}
+
+fn upgrade_item(item: &mut Item, schema: &Schema, version: i64) {
+    match schema {
+        Schema::Array(schema) => {
+            if let Some(array) = item.as_array_of_tables_mut() {
+                for table in array.iter_mut() {
+                    upgrade_table(table, schema, version);
+                }
+            } else if let Some(array) = item.as_array_mut() {
+                for value in array.iter_mut() {
+                    if let Some(table) = value.as_inline_table_mut() {
+                        upgrade_table(table, schema, version);
+                    }
+                }
+            }
+        }
+        schema => {
+            if let Some(table) = item.as_table_like_mut() {
+                upgrade_table(table, schema, version);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use vgpu_unlock_schema::migrate::Rename;
+    use vgpu_unlock_schema::schema::Schema;
+
+    use super::{upgrade, upgrade_to};
+
+    // No key has been renamed yet, so the tests use a version 2 of their own.
+    static RENAMES: &[Rename] = &[Rename {
+        since: 2,
+        from: "gpu_type",
+        to: "vgpu_type_id",
+    }];
+    static ENTRY_FIELDS: Schema = Schema::Struct(&[], &[]);
+    static ROOT: Schema = Schema::Struct(&["profile"], &[("profile", Schema::Map(&PROFILE))]);
+    static PROFILE: Schema = Schema::Renamed(RENAMES, &ENTRY_FIELDS);
+
+    #[test]
+    fn test_upgrade() {
+        let data = "# Overrides\n\
+                    [profile.nvidia-55]\n\
+                    # The type\n\
+                    gpu_type = 1 # inline\n\
+                    num_displays = 1\n\
+                    \n\
+                    [profile.nvidia-56]\n\
+                    gpu_type = 1\n\
+                    vgpu_type_id = 2\n\
+                    [mdev.x]\n\
+                    gpu_type = 3\n";
+
+        assert_eq!(
+            upgrade_to(data, &ROOT, 2).ok().unwrap(),
+            "version = 2\n\
+             # Overrides\n\
+             [profile.nvidia-55]\n\
+             # The type\n\
+             vgpu_type_id = 1 # inline\n\
+             num_displays = 1\n\
+             \n\
+             [profile.nvidia-56]\n\
+             vgpu_type_id = 2\n\
+             [mdev.x]\n\
+             gpu_type = 3\n"
+        );
+        assert_eq!(
+            upgrade_to("version = 2\n[profile.a]\ngpu_type = 1\n", &ROOT, 2)
+                .ok()
+                .unwrap(),
+            "version = 2\n[profile.a]\ngpu_type = 1\n"
+        );
+        assert!(upgrade_to("version = 3\n", &ROOT, 2).is_err());
+
+        assert_eq!(
+            upgrade("[profile.a]\nnum_displays = 1\n", &ROOT)
+                .ok()
+                .unwrap(),
+            "version = 1\n[profile.a]\nnum_displays = 1\n"
+        );
+        assert!(upgrade("version = 2\n", &ROOT).is_err());
+    }
+}
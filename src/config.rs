@@ -1,10 +1,33 @@
 // SPDX-License-Identifier: MIT
 
 use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use parking_lot::{Mutex, RwLock};
 use serde::Deserialize;
+use toml::{Table, Value};
 
+use crate::layered::{self, FileStamp, Layered, LoadError, Source};
+use crate::log::{error, info};
+use crate::overlay;
+use crate::permissions::{self, StrictModes};
+use crate::schema;
 use crate::string_number::U32;
+use crate::utils;
+use crate::validate::Strictness;
+
+/// Minimum time between two checks of the configuration files for changes.
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Copy of the last configuration that loaded successfully, used by
+/// [`FailurePolicy::LastKnownGood`].
+pub const LAST_KNOWN_GOOD_PATH: &str = "/var/lib/vgpu_unlock/config.toml";
 
 struct Defaults;
 
@@ -28,6 +51,54 @@ pub struct Config {
     pub unlock_migration: bool,
     #[serde(default)]
     pub pci_info_map: Option<HashMap<U32, PciInfoMapEntry>>,
+    #[serde(default)]
+    pub features: Features,
+}
+
+/// Independent toggles for each behavior of the library. Unset toggles for the spoofing
+/// behaviors follow `unlock`, the workarounds are enabled unless disabled explicitly.
+#[derive(Debug, Default, Deserialize)]
+pub struct Features {
+    /// Report the PCI device ID of a vGPU capable card from `NV2080_CTRL_CMD_BUS_GET_PCI_INFO`.
+    pub spoof_pci_info: Option<bool>,
+    /// Report the GPU as vGPU capable from `NV0080_CTRL_CMD_GPU_GET_VIRTUALIZATION_MODE`.
+    pub spoof_virtualization_mode: Option<bool>,
+    /// Clear the failure status of command `0xa0820104`.
+    pub ignore_a0820104_failure: Option<bool>,
+    /// Clear the failure status of `NV9096_CTRL_CMD_GET_ZBC_CLEAR_TABLE`.
+    pub ignore_zbc_clear_table_failure: Option<bool>,
+    /// Report a missing inforom object instead of an unsupported command from
+    /// `NV2080_CTRL_CMD_GPU_GET_INFOROM_OBJECT_VERSION`, needed by some Maxwell cards.
+    pub inforom_workaround: Option<bool>,
+}
+
+impl Config {
+    #[inline]
+    pub fn spoof_pci_info(&self) -> bool {
+        self.features.spoof_pci_info.unwrap_or(self.unlock)
+    }
+
+    #[inline]
+    pub fn spoof_virtualization_mode(&self) -> bool {
+        self.features
+            .spoof_virtualization_mode
+            .unwrap_or(self.unlock)
+    }
+
+    #[inline]
+    pub fn ignore_a0820104_failure(&self) -> bool {
+        self.features.ignore_a0820104_failure.unwrap_or(true)
+    }
+
+    #[inline]
+    pub fn ignore_zbc_clear_table_failure(&self) -> bool {
+        self.features.ignore_zbc_clear_table_failure.unwrap_or(true)
+    }
+
+    #[inline]
+    pub fn inforom_workaround(&self) -> bool {
+        self.features.inforom_workaround.unwrap_or(true)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -43,6 +114,441 @@ impl Default for Config {
             unlock: Defaults::unlock(),
             unlock_migration: Defaults::unlock_migration(),
             pci_info_map: None,
+            features: Default::default(),
+        }
+    }
+}
+
+/// What to do when the configuration fails to load at startup, selected with the
+/// `VGPU_UNLOCK_CONFIG_FAILURE_POLICY` environment variable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Abort the process. This is the default.
+    Abort,
+    /// Continue with the default configuration.
+    Defaults,
+    /// Continue with the copy of the last configuration that loaded successfully, or the
+    /// default configuration if there is none.
+    LastKnownGood,
+}
+
+impl FailurePolicy {
+    pub fn from_env() -> Self {
+        let value = match env::var("VGPU_UNLOCK_CONFIG_FAILURE_POLICY") {
+            Ok(value) => value,
+            Err(_) => return FailurePolicy::Abort,
+        };
+
+        match value.trim() {
+            "abort" => FailurePolicy::Abort,
+            "defaults" => FailurePolicy::Defaults,
+            "last-known-good" => FailurePolicy::LastKnownGood,
+            value => {
+                error!(
+                    "Unknown VGPU_UNLOCK_CONFIG_FAILURE_POLICY '{}', expected one of 'abort', \
+                     'defaults' or 'last-known-good'",
+                    value
+                );
+
+                FailurePolicy::Abort
+            }
+        }
+    }
+}
+
+pub enum ConfigError {
+    Load(LoadError),
+    Decode(toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Load(e) => fmt::Display::fmt(e, f),
+            ConfigError::Decode(e) => write!(f, "Failed to decode config: {}", e),
+        }
+    }
+}
+
+/// A configuration along with the tables it was decoded from.
+struct Loaded {
+    config: Config,
+    /// The merged table including the per-process section and the environment variable overlay.
+    table: Table,
+    /// The merged files before the per-process section and the environment variable overlay are
+    /// applied, `None` if there are no files.
+    files: Option<Table>,
+}
+
+/// Loads the configuration at `path` along with its drop-ins and the environment variable
+/// overlay.
+fn load(path: &Path) -> Result<Loaded, ConfigError> {
+    let layered = layered::load(
+        path,
+        &schema::CONFIG,
+        Strictness::from_env(),
+        StrictModes::from_env(),
+    )
+    .map_err(ConfigError::Load)?;
+    let files = layered.as_ref().map(|layered| layered.files.clone());
+    let (config, table) = decode(layered.unwrap_or_default())?;
+
+    Ok(Loaded {
+        config,
+        table,
+        files,
+    })
+}
+
+/// Applies the environment variable overlay on top of `layered` and decodes the result.
+fn decode(mut layered: Layered) -> Result<(Config, Table), ConfigError> {
+    overlay::apply(&mut layered, &schema::CONFIG);
+    layered.log_sources();
+
+    let config = Value::Table(layered.table.clone())
+        .try_into()
+        .map_err(ConfigError::Decode)?;
+
+    Ok((config, layered.table))
+}
+
+fn load_last_known_good(path: &Path) -> Result<(Config, Table), ConfigError> {
+    permissions::enforce(path, StrictModes::from_env())
+        .map_err(|e| ConfigError::Load(LoadError::Insecure(path.to_path_buf(), e)))?;
+
+    let data = fs::read_to_string(path)
+        .map_err(|e| ConfigError::Load(LoadError::Read(path.to_path_buf(), e)))?;
+    let table: Table = toml::from_str(&data)
+        .map_err(|e| ConfigError::Load(LoadError::Parse(path.to_path_buf(), e)))?;
+
+    decode(layered::load_files(
+        table,
+        &Source::File(path.to_path_buf()),
+    ))
+}
+
+/// Saves the merged `files` of a configuration that loaded successfully to `path`.
+///
+/// The per-process sections are kept and the environment variables are left out, as the copy is
+/// shared by both daemons and each applies its own when falling back to it.
+fn save_last_known_good(path: &Path, files: &Table) {
+    let data = files.to_string();
+
+    if fs::read_to_string(path).ok().as_ref() == Some(&data) {
+        return;
+    }
+
+    if let Err(e) = utils::write_atomic(path, &data) {
+        error!(
+            "Failed to save last known good config to '{}': {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+/// The global configuration, reloaded when any of the files it was read from change.
+///
+/// The files are checked at most once every [`RELOAD_CHECK_INTERVAL`]. A configuration that
+/// fails to load is ignored and the last one that loaded successfully stays in use.
+pub struct SharedConfig {
+    path: PathBuf,
+    last_known_good_path: PathBuf,
+    current: RwLock<Arc<Config>>,
+    state: Mutex<ReloadState>,
+}
+
+struct ReloadState {
+    last_check: Instant,
+    /// Stamps of the files as of the last load attempt, successful or not.
+    stamps: Vec<FileStamp>,
+    /// Merged table of the last configuration that loaded successfully.
+    table: Table,
+}
+
+impl SharedConfig {
+    /// Loads the configuration at `path`, falling back according to `policy` if that fails.
+    ///
+    /// Every configuration that loads successfully from at least one file is saved to
+    /// `last_known_good_path`.
+    pub fn load(path: &Path, last_known_good_path: &Path, policy: FailurePolicy) -> Self {
+        let stamps = layered::stamps(path).map_err(ConfigError::Load);
+        let result = stamps.and_then(|stamps| Ok((load(path)?, stamps)));
+
+        match result {
+            Ok((loaded, stamps)) => {
+                if let Some(files) = &loaded.files {
+                    save_last_known_good(last_known_good_path, files);
+                }
+
+                Self::new(
+                    path,
+                    last_known_good_path,
+                    loaded.config,
+                    loaded.table,
+                    stamps,
+                )
+            }
+            Err(e) => {
+                error!("{}", e);
+
+                Self::load_fallback(path, last_known_good_path, policy)
+            }
+        }
+    }
+
+    fn load_fallback(path: &Path, last_known_good_path: &Path, policy: FailurePolicy) -> Self {
+        // Remember the files that failed to load so they are only tried again once they change.
+        let stamps = layered::stamps(path).unwrap_or_default();
+
+        match policy {
+            FailurePolicy::Abort => {
+                error!("Aborting as VGPU_UNLOCK_CONFIG_FAILURE_POLICY is 'abort'");
+
+                process::abort();
+            }
+            FailurePolicy::Defaults => {
+                error!(
+                    "Using the default config as VGPU_UNLOCK_CONFIG_FAILURE_POLICY is 'defaults'"
+                );
+            }
+            FailurePolicy::LastKnownGood => match load_last_known_good(last_known_good_path) {
+                Ok((config, table)) => {
+                    error!(
+                        "Using the last known good config from '{}'",
+                        last_known_good_path.display()
+                    );
+
+                    return Self::new(path, last_known_good_path, config, table, stamps);
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    error!("Using the default config as there is no last known good config");
+                }
+            },
+        }
+
+        Self::new(
+            path,
+            last_known_good_path,
+            Default::default(),
+            Table::new(),
+            stamps,
+        )
+    }
+
+    fn new(
+        path: &Path,
+        last_known_good_path: &Path,
+        config: Config,
+        table: Table,
+        stamps: Vec<FileStamp>,
+    ) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            last_known_good_path: last_known_good_path.to_path_buf(),
+            current: RwLock::new(Arc::new(config)),
+            state: Mutex::new(ReloadState {
+                last_check: Instant::now(),
+                stamps,
+                table,
+            }),
         }
     }
+
+    /// Returns the current configuration, reloading it first if the files changed.
+    pub fn get(&self) -> Arc<Config> {
+        self.reload_if_changed();
+
+        self.current.read().clone()
+    }
+
+    fn reload_if_changed(&self) {
+        // Another thread is already checking, use the current configuration.
+        let mut state = match self.state.try_lock() {
+            Some(state) => state,
+            None => return,
+        };
+
+        if state.last_check.elapsed() < RELOAD_CHECK_INTERVAL {
+            return;
+        }
+        state.last_check = Instant::now();
+
+        let stamps = match layered::stamps(&self.path) {
+            Ok(stamps) => stamps,
+            Err(e) => {
+                error!("Failed to check config for changes: {}", e);
+                return;
+            }
+        };
+
+        if stamps == state.stamps {
+            return;
+        }
+        state.stamps = stamps;
+
+        match load(&self.path) {
+            Ok(loaded) => {
+                info!("Reloaded config from '{}'", self.path.display());
+
+                for change in layered::diff(&state.table, &loaded.table) {
+                    info!("Config changed: {}", change);
+                }
+
+                if let Some(files) = &loaded.files {
+                    save_last_known_good(&self.last_known_good_path, files);
+                }
+
+                state.table = loaded.table;
+                *self.current.write() = Arc::new(loaded.config);
+            }
+            Err(e) => {
+                error!("{}", e);
+                error!("Keeping the previously loaded config");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::time::Instant;
+
+    use toml::{Table, Value};
+
+    use super::{
+        Config, FailurePolicy, Features, PciInfoMapEntry, SharedConfig, RELOAD_CHECK_INTERVAL,
+    };
+    use crate::schema;
+    use crate::validate;
+
+    #[test]
+    fn test_schema_fields() {
+        assert_eq!(schema::CONFIG_FIELDS, validate::fields::<Config>());
+        assert_eq!(schema::FEATURES_FIELDS, validate::fields::<Features>());
+        assert_eq!(
+            schema::PCI_INFO_MAP_ENTRY_FIELDS,
+            validate::fields::<PciInfoMapEntry>()
+        );
+    }
+
+    #[test]
+    fn test_deserialize_from_value() {
+        let value: Value = toml::from_str(
+            "unlock = false\n[pci_info_map.0x1e84]\ndevice_id = 0x1e30\nsub_system_id = 0x12ba\n",
+        )
+        .unwrap();
+        let config: Config = value.try_into().unwrap();
+
+        assert!(!config.unlock);
+        assert!(!config.unlock_migration);
+
+        let pci_info_map = config.pci_info_map.unwrap();
+        let entry = pci_info_map.values().next().unwrap();
+        assert!(pci_info_map.keys().all(|k| *k == 0x1e84));
+        assert_eq!(entry.device_id, 0x1e30);
+        assert_eq!(entry.sub_system_id, 0x12ba);
+    }
+
+    #[test]
+    fn test_features() {
+        let config: Config = toml::from_str("unlock = false\n").unwrap();
+        assert!(!config.spoof_pci_info());
+        assert!(!config.spoof_virtualization_mode());
+        assert!(config.ignore_a0820104_failure());
+        assert!(config.ignore_zbc_clear_table_failure());
+        assert!(config.inforom_workaround());
+
+        let config: Config = toml::from_str(
+            "[features]\nspoof_virtualization_mode = false\ninforom_workaround = false\n",
+        )
+        .unwrap();
+        assert!(config.spoof_pci_info());
+        assert!(!config.spoof_virtualization_mode());
+        assert!(!config.inforom_workaround());
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = env::temp_dir().join(format!("vgpu_unlock-test-reload-{}", process::id()));
+        let path = dir.join("config.toml");
+        let expire = |config: &SharedConfig| {
+            config.state.lock().last_check = Instant::now() - RELOAD_CHECK_INTERVAL;
+        };
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "unlock = false\n").unwrap();
+
+        let config = SharedConfig::load(
+            &path,
+            &dir.join("last_known_good.toml"),
+            FailurePolicy::Abort,
+        );
+        assert!(!config.get().unlock);
+
+        fs::write(&path, "unlock = true\nunlock_migration = true\n").unwrap();
+        assert!(
+            !config.get().unlock,
+            "changes are only picked up after the interval"
+        );
+
+        expire(&config);
+        assert!(config.get().unlock);
+        assert!(config.get().unlock_migration);
+
+        // A config that fails to decode keeps the last one that loaded successfully.
+        fs::write(&path, "unlock = 'yes'\n").unwrap();
+        expire(&config);
+        assert!(config.get().unlock);
+        assert!(config.get().unlock_migration);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failure_policy() {
+        let dir = env::temp_dir().join(format!("vgpu_unlock-test-policy-{}", process::id()));
+        let path = dir.join("config.toml");
+        let last_known_good_path = dir.join("last_known_good.toml");
+
+        fs::create_dir_all(&dir).unwrap();
+
+        // Nothing is saved without a config file.
+        SharedConfig::load(&path, &last_known_good_path, FailurePolicy::Abort);
+        assert!(!last_known_good_path.exists());
+
+        fs::write(
+            &path,
+            "unlock = false\n[process.default]\nunlock_migration = true\n",
+        )
+        .unwrap();
+
+        SharedConfig::load(&path, &last_known_good_path, FailurePolicy::Abort);
+        let saved: Table =
+            toml::from_str(&fs::read_to_string(&last_known_good_path).unwrap()).unwrap();
+        assert!(
+            saved.contains_key("process"),
+            "saved before the process section is applied"
+        );
+        assert!(fs::read_dir(&dir).unwrap().all(|entry| {
+            let name = entry.unwrap().file_name();
+            !name.to_string_lossy().ends_with(".tmp")
+        }));
+
+        fs::write(&path, "unlock = false\nunlock_migration = 'yes'\n").unwrap();
+
+        let config = SharedConfig::load(&path, &last_known_good_path, FailurePolicy::Defaults);
+        assert!(config.get().unlock);
+        assert!(!config.get().unlock_migration);
+
+        let config = SharedConfig::load(&path, &last_known_good_path, FailurePolicy::LastKnownGood);
+        assert!(!config.get().unlock);
+        assert!(config.get().unlock_migration);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: MIT
+
+//! Consistency checks of a vGPU profile after the overrides are applied.
+//!
+//! Each override sets its field on its own, so a set of overrides can produce a combination of
+//! values the driver rejects or misbehaves on. The checks run on the final state of the profile
+//! and report every violation by name.
+
+use std::env;
+use std::fmt;
+
+use crate::log::error;
+use crate::VgpuConfigLike;
+
+/// What to do with a profile that fails the checks, selected with the
+/// `VGPU_UNLOCK_PROFILE_CHECK` environment variable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Log the violations and keep the overrides. This is the default.
+    Warn,
+    /// Log the violations and report the profile as the driver did, without any overrides.
+    Reject,
+    /// Log the violations and fail the ioctl.
+    Fail,
+}
+
+impl Action {
+    pub fn from_env() -> Self {
+        let value = match env::var("VGPU_UNLOCK_PROFILE_CHECK") {
+            Ok(value) => value,
+            Err(_) => return Action::Warn,
+        };
+
+        match value.trim() {
+            "warn" => Action::Warn,
+            "reject" => Action::Reject,
+            "fail" => Action::Fail,
+            value => {
+                error!(
+                    "Unknown VGPU_UNLOCK_PROFILE_CHECK '{}', expected 'warn', 'reject' or 'fail'",
+                    value
+                );
+
+                Action::Warn
+            }
+        }
+    }
+}
+
+/// A failed check.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub name: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.message)
+    }
+}
+
+/// Checks `config` after the overrides are applied. `original` is the profile as reported by the
+/// driver, which bounds the memory available to the profile.
+pub fn check<C: VgpuConfigLike>(config: &mut C, original: &mut C) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    let fb_length = *config.fb_length();
+    let fb_reservation = *config.fb_reservation();
+
+    if fb_reservation > fb_length {
+        violations.push(Violation {
+            name: "fb_reservation_exceeds_fb_length",
+            message: format!(
+                "fb_reservation {:#x} is larger than fb_length {:#x}",
+                fb_reservation, fb_length
+            ),
+        });
+    }
+
+    // `bar1_length` is in MiB.
+    let mappable_video_size = *config.mappable_video_size();
+    let bar1_length = *config.bar1_length();
+
+    if u128::from(mappable_video_size) > u128::from(bar1_length) << 20 {
+        violations.push(Violation {
+            name: "mappable_video_size_exceeds_bar1_length",
+            message: format!(
+                "mappable_video_size {:#x} is larger than bar1_length {} MiB",
+                mappable_video_size, bar1_length
+            ),
+        });
+    }
+
+    // The driver sizes its profiles to fill the memory of the GPU, so the memory of all instances
+    // of the original profile is the best estimate of the memory available.
+    let total = |config: &mut C| {
+        u128::from(*config.max_instance())
+            * (u128::from(*config.fb_length()) + u128::from(*config.fb_reservation()))
+    };
+    let available = total(original);
+    let required = total(config);
+
+    if available != 0 && required > available {
+        violations.push(Violation {
+            name: "framebuffer_exceeds_physical_memory",
+            message: format!(
+                "{} instances need {:#x} bytes, the GPU has about {:#x} bytes",
+                config.max_instance(),
+                required,
+                available
+            ),
+        });
+    }
+
+    let max_pixels = *config.max_pixels();
+    let max_resolution_x = *config.max_resolution_x();
+    let max_resolution_y = *config.max_resolution_y();
+
+    if u64::from(max_pixels) < u64::from(max_resolution_x) * u64::from(max_resolution_y) {
+        violations.push(Violation {
+            name: "max_pixels_below_max_resolution",
+            message: format!(
+                "max_pixels {} does not fit a single display at {}x{}",
+                max_pixels, max_resolution_x, max_resolution_y
+            ),
+        });
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem;
+
+    use super::check;
+    use crate::nvidia::ctrla082::NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525;
+
+    #[test]
+    fn test_check() {
+        let mut original: NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 =
+            unsafe { mem::zeroed() };
+        original.max_instance = 12;
+        original.fb_length = 0x7400_0000;
+        original.fb_reservation = 0xc00_0000;
+        original.mappable_video_size = 0x40_0000;
+        original.bar1_length = 0x100;
+        original.max_resolution_x = 1280;
+        original.max_resolution_y = 1024;
+        original.max_pixels = 1280 * 1024;
+
+        let mut config = original.clone();
+        assert!(check(&mut config, &mut original).is_empty());
+
+        // Fewer instances with more memory each still fit.
+        config.max_instance = 6;
+        config.fb_length = 0xf400_0000;
+        assert!(check(&mut config, &mut original).is_empty());
+
+        config.max_instance = 12;
+        config.fb_reservation = 0x1_0000_0000;
+        config.mappable_video_size = 0x1000_0001;
+        config.max_resolution_x = 1920;
+        config.max_resolution_y = 1080;
+
+        let names: Vec<_> = check(&mut config, &mut original)
+            .into_iter()
+            .map(|violation| violation.name)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "fb_reservation_exceeds_fb_length",
+                "mappable_video_size_exceeds_bar1_length",
+                "framebuffer_exceeds_physical_memory",
+                "max_pixels_below_max_resolution",
+            ]
+        );
+    }
+}
@@ -0,0 +1,275 @@
+// SPDX-License-Identifier: MIT
+
+//! Provenance of the fields of patched vGPU profiles.
+//!
+//! Every field changed by the overrides is recorded with its original value, its final value,
+//! and the rule and file that set it. After each query of a vGPU profile the record is logged as
+//! one summary block and written to [`EXPLAIN_DIR`] as a TOML file named after the mdev UUID of
+//! the vGPU, or after the vGPU type when the mdev is not known. The file is only written when the
+//! explanation changed since the last query and removed once no field is changed, so it always
+//! describes the profile last reported to the driver.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use parking_lot::Mutex;
+use toml::{Table, Value};
+
+use crate::layered::Source;
+use crate::log::{error, info};
+use crate::utils;
+#[cfg(feature = "proxmox")]
+use crate::utils::uuid_to_vmid;
+use crate::uuid::Uuid;
+
+/// Directory the explanations are written to.
+pub const EXPLAIN_DIR: &str = "/run/vgpu_unlock/explain";
+
+/// The origin of the final value of a field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldOrigin {
+    /// The value as reported by the driver.
+    pub original: String,
+    pub value: String,
+    /// The rule that set the value, e.g. `proxmox VMID 104`.
+    pub rule: String,
+    /// Where the rule set the value, if known.
+    pub source: Option<Source>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Explanation {
+    pub vgpu_type: String,
+    pub mdev: Option<Uuid>,
+    /// The origin of every changed field, keyed by the name of the field in the profile.
+    pub fields: BTreeMap<&'static str, FieldOrigin>,
+}
+
+impl Explanation {
+    pub fn new(vgpu_type: String, mdev: Option<Uuid>) -> Self {
+        Explanation {
+            vgpu_type,
+            mdev,
+            fields: BTreeMap::new(),
+        }
+    }
+
+    /// Records that `rule` changed `field` from `old` to `new`. The original value is the one
+    /// before the first change.
+    pub fn record(
+        &mut self,
+        field: &'static str,
+        old: String,
+        new: String,
+        rule: &str,
+        source: Option<&Source>,
+    ) {
+        let origin = self.fields.entry(field).or_insert_with(|| FieldOrigin {
+            original: old,
+            value: String::new(),
+            rule: String::new(),
+            source: None,
+        });
+
+        origin.value = new;
+        origin.rule = rule.to_string();
+        origin.source = source.cloned();
+    }
+
+    /// The name the explanation is stored under, the mdev UUID or the vGPU type.
+    pub fn key(&self) -> String {
+        match self.mdev {
+            Some(mdev) => mdev.to_string(),
+            None => self.vgpu_type.clone(),
+        }
+    }
+
+    /// Logs the explanation as one block.
+    pub fn log(&self) {
+        if self.fields.is_empty() {
+            return;
+        }
+
+        match self.mdev {
+            Some(mdev) => info!("Overrides of {} for mdev {}:", self.vgpu_type, mdev),
+            None => info!("Overrides of {}:", self.vgpu_type),
+        }
+
+        for (field, origin) in &self.fields {
+            match &origin.source {
+                Some(source) => info!(
+                    "  {}: {} -> {} by {} in {}",
+                    field, origin.original, origin.value, origin.rule, source
+                ),
+                None => info!(
+                    "  {}: {} -> {} by {}",
+                    field, origin.original, origin.value, origin.rule
+                ),
+            }
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut table = Table::new();
+
+        table.insert("vgpu_type".into(), self.vgpu_type.clone().into());
+
+        if let Some(mdev) = self.mdev {
+            table.insert("mdev".into(), mdev.to_string().into());
+
+            #[cfg(feature = "proxmox")]
+            if let Some(vmid) = uuid_to_vmid(mdev) {
+                table.insert("vmid".into(), Value::Integer(vmid as i64));
+            }
+        }
+
+        let mut fields = Table::new();
+
+        for (field, origin) in &self.fields {
+            let mut entry = Table::new();
+
+            entry.insert("original".into(), origin.original.clone().into());
+            entry.insert("value".into(), origin.value.clone().into());
+            entry.insert("rule".into(), origin.rule.clone().into());
+
+            match &origin.source {
+                Some(Source::File(path)) => {
+                    entry.insert("file".into(), path.display().to_string().into());
+                }
+                Some(Source::Env(name)) => {
+                    entry.insert("env".into(), name.clone().into());
+                }
+                None => {}
+            }
+
+            fields.insert(field.to_string(), Value::Table(entry));
+        }
+
+        table.insert("fields".into(), Value::Table(fields));
+        table.to_string()
+    }
+}
+
+/// The explanations last written to [`EXPLAIN_DIR`], keyed by [`Explanation::key`].
+static PUBLISHED: Mutex<BTreeMap<String, Explanation>> = parking_lot::const_mutex(BTreeMap::new());
+
+/// Logs `explanation` and writes it to [`EXPLAIN_DIR`] if it changed.
+pub fn publish(explanation: &Explanation) {
+    explanation.log();
+    update(Path::new(EXPLAIN_DIR), &mut PUBLISHED.lock(), explanation);
+}
+
+/// Writes `explanation` to `dir` unless it is the same as the one last written for its key, as
+/// recorded in `published`. The file is removed once the overrides no longer change any field.
+fn update(dir: &Path, published: &mut BTreeMap<String, Explanation>, explanation: &Explanation) {
+    let key = explanation.key();
+    let unchanged = match published.get(&key) {
+        Some(last) => last == explanation,
+        None => explanation.fields.is_empty(),
+    };
+
+    if unchanged {
+        return;
+    }
+
+    let path = dir.join(format!("{}.toml", key));
+
+    // Remember the explanation even if it fails to be written, the error is only logged once.
+    let result = if explanation.fields.is_empty() {
+        published.remove(&key);
+
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    } else {
+        published.insert(key, explanation.clone());
+
+        utils::write_atomic(&path, &explanation.to_toml())
+    };
+
+    if let Err(e) = result {
+        error!(
+            "Failed to save the explanation to '{}': {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::{update, Explanation};
+    use crate::layered::Source;
+    use crate::uuid::Uuid;
+
+    #[test]
+    fn test_explanation() {
+        let file = Source::File(PathBuf::from("/etc/vgpu_unlock/profile_override.toml"));
+        let mut explanation = Explanation::new(
+            "nvidia-55".to_string(),
+            Some(Uuid(0, 0, 0, [0, 0, 0, 0, 0, 0, 0x01, 0x04])),
+        );
+
+        explanation.record(
+            "num_heads",
+            "1".into(),
+            "2".into(),
+            "profile nvidia-55",
+            None,
+        );
+        explanation.record(
+            "num_heads",
+            "2".into(),
+            "4".into(),
+            "proxmox VMID 104",
+            Some(&file),
+        );
+
+        let origin = &explanation.fields["num_heads"];
+        assert_eq!(origin.original, "1");
+        assert_eq!(origin.value, "4");
+        assert_eq!(origin.rule, "proxmox VMID 104");
+        assert_eq!(origin.source, Some(file));
+
+        let dir = env::temp_dir().join(format!("vgpu_unlock-test-explain-{}", process::id()));
+        let path = dir.join("00000000-0000-0000-0000-000000000104.toml");
+        let mut published = BTreeMap::new();
+        update(&dir, &mut published, &explanation);
+
+        let data = fs::read_to_string(&path).unwrap();
+        let table: toml::Table = toml::from_str(&data).unwrap();
+        assert_eq!(table["vgpu_type"].as_str(), Some("nvidia-55"));
+        assert_eq!(
+            table["fields"]["num_heads"]["file"].as_str(),
+            Some("/etc/vgpu_unlock/profile_override.toml")
+        );
+        #[cfg(feature = "proxmox")]
+        assert_eq!(table["vmid"].as_integer(), Some(104));
+
+        // The same explanation is not written again.
+        fs::write(&path, "").unwrap();
+        update(&dir, &mut published, &explanation);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+        // Nothing is written for a profile without overrides, and the last file is removed.
+        let unchanged = Explanation::new("nvidia-56".to_string(), None);
+        update(&dir, &mut published, &unchanged);
+        assert!(!dir.join("nvidia-56.toml").exists());
+
+        explanation.fields.clear();
+        update(&dir, &mut published, &explanation);
+        assert!(!path.exists());
+        assert!(published.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
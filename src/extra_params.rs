@@ -0,0 +1,136 @@
+// SPDX-License-Identifier: MIT
+
+//! `vgpu_extra_params` of vGPU profiles. The parameters are 1024 words in the profiles of
+//! `nvidia-vgpud` and 1024 bytes in the profiles of `nvidia-vgpu-mgr`.
+//!
+//! The parameters are only known by index. Decoding them by name needs names taken from a source
+//! for what each index means, and NVIDIA publishes none, so it is not implemented.
+
+use std::convert::TryInto;
+use std::fmt;
+
+use crate::format::HexFormat;
+
+/// Parses the index of a parameter, written in decimal or in hexadecimal with a `0x` prefix.
+pub fn index(key: &str) -> Option<usize> {
+    let key = key.trim();
+
+    if let Some(hex) = key.strip_prefix("0x").or_else(|| key.strip_prefix("0X")) {
+        return usize::from_str_radix(hex, 16).ok();
+    }
+
+    key.parse().ok()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetError {
+    OutOfBounds { len: usize },
+    TooLarge { max: u32 },
+}
+
+impl fmt::Display for SetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SetError::OutOfBounds { len } => {
+                write!(f, "index is out of bounds, there are {} parameters", len)
+            }
+            SetError::TooLarge { max } => write!(f, "value is larger than {:#x}", max),
+        }
+    }
+}
+
+/// The parameters of a profile, in the element width of its layout.
+pub enum ExtraParams<'a> {
+    Words(&'a mut [u32]),
+    Bytes(&'a mut [u8]),
+}
+
+impl ExtraParams<'_> {
+    pub fn get(&self, index: usize) -> Option<u32> {
+        match self {
+            ExtraParams::Words(params) => params.get(index).copied(),
+            ExtraParams::Bytes(params) => params.get(index).copied().map(u32::from),
+        }
+    }
+
+    pub fn set(&mut self, index: usize, value: u32) -> Result<(), SetError> {
+        match self {
+            ExtraParams::Words(params) => {
+                let len = params.len();
+                let param = params.get_mut(index).ok_or(SetError::OutOfBounds { len })?;
+
+                *param = value;
+            }
+            ExtraParams::Bytes(params) => {
+                let len = params.len();
+                let param = params.get_mut(index).ok_or(SetError::OutOfBounds { len })?;
+
+                *param = value.try_into().map_err(|_| SetError::TooLarge {
+                    max: u8::MAX.into(),
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ExtraParams<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExtraParams::Words(params) => fmt::Debug::fmt(&ExtraParamsFormat(&params[..]), f),
+            ExtraParams::Bytes(params) => fmt::Debug::fmt(&ExtraParamsFormat(&params[..]), f),
+        }
+    }
+}
+
+/// Formats the non-zero parameters by index.
+pub struct ExtraParamsFormat<'a, T>(pub &'a [T]);
+
+impl<'a, T: Copy + Into<u32>> fmt::Debug for ExtraParamsFormat<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut map = f.debug_map();
+
+        for (index, &value) in self.0.iter().enumerate() {
+            let value = value.into();
+
+            if value == 0 {
+                continue;
+            }
+
+            map.entry(&index, &HexFormat(value));
+        }
+
+        map.finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{index, ExtraParams, ExtraParamsFormat, SetError};
+
+    #[test]
+    fn test_extra_params() {
+        assert_eq!(index("17"), Some(17));
+        assert_eq!(index("0x11"), Some(17));
+        assert_eq!(index("seventeen"), None);
+
+        let mut words = [0u32; 4];
+        let mut params = ExtraParams::Words(&mut words);
+        assert_eq!(params.set(3, 0x1_0000), Ok(()));
+        assert_eq!(params.get(3), Some(0x1_0000));
+        assert_eq!(params.set(4, 1), Err(SetError::OutOfBounds { len: 4 }));
+
+        let mut bytes = [0u8; 4];
+        let mut params = ExtraParams::Bytes(&mut bytes);
+        assert_eq!(params.set(1, 0x40), Ok(()));
+        assert_eq!(params.set(2, 0x100), Err(SetError::TooLarge { max: 0xff }));
+        assert_eq!(format!("{:?}", params), "{1: 0x40}");
+
+        assert_eq!(
+            format!("{:?}", ExtraParamsFormat(&words[..])),
+            "{3: 0x10000}"
+        );
+        assert_eq!(format!("{:?}", ExtraParamsFormat(&[0u8; 4][..])), "{}");
+    }
+}
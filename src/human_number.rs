@@ -5,7 +5,77 @@ use std::fmt;
 
 use serde::de::{Deserializer, Error, Unexpected, Visitor};
 
-pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+/// A number in an override, either absolute or computed from the values reported by the driver.
+///
+/// Besides plain and human-readable numbers such as `"512MiB"`, strings can combine numbers and
+/// the names of other fields with `+`, `-`, `*`, `/` and parentheses, e.g.
+/// `"display_width*display_height"`. A leading operator makes the value relative to the original
+/// value of the field it is set for, e.g. `"+512MiB"` or `"*2"`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Number(u64),
+    /// The original value of the field being set.
+    Current,
+    /// The original value of another field.
+    Field(String),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EvalError {
+    Overflow,
+    Underflow,
+    DivisionByZero,
+    UnknownField(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::Overflow => f.write_str("the result is too large"),
+            EvalError::Underflow => f.write_str("the result is negative"),
+            EvalError::DivisionByZero => f.write_str("division by zero"),
+            EvalError::UnknownField(name) => write!(f, "unknown field `{}`", name),
+        }
+    }
+}
+
+impl Expr {
+    /// Evaluates the expression with checked arithmetic. `current` is the original value of the
+    /// field being set, and `field` returns the original value of any other field.
+    pub fn eval(
+        &self,
+        current: u64,
+        field: &dyn Fn(&str) -> Option<u64>,
+    ) -> Result<u64, EvalError> {
+        match self {
+            Expr::Number(v) => Ok(*v),
+            Expr::Current => Ok(current),
+            Expr::Field(name) => field(name).ok_or_else(|| EvalError::UnknownField(name.clone())),
+            Expr::Binary(lhs, op, rhs) => {
+                let lhs = lhs.eval(current, field)?;
+                let rhs = rhs.eval(current, field)?;
+
+                match op {
+                    Op::Add => lhs.checked_add(rhs).ok_or(EvalError::Overflow),
+                    Op::Sub => lhs.checked_sub(rhs).ok_or(EvalError::Underflow),
+                    Op::Mul => lhs.checked_mul(rhs).ok_or(EvalError::Overflow),
+                    Op::Div => lhs.checked_div(rhs).ok_or(EvalError::DivisionByZero),
+                }
+            }
+        }
+    }
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Expr>, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -15,10 +85,11 @@ where
 struct HumanNumberVisitor;
 
 impl<'de> Visitor<'de> for HumanNumberVisitor {
-    type Value = Option<u64>;
+    type Value = Option<Expr>;
 
     fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
-        formatter.write_str("unsigned number or quoted human-readable unsigned number")
+        formatter
+            .write_str("unsigned number or quoted human-readable unsigned number or expression")
     }
 
     fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
@@ -34,7 +105,7 @@ impl<'de> Visitor<'de> for HumanNumberVisitor {
     where
         E: Error,
     {
-        Ok(Some(v))
+        Ok(Some(Expr::Number(v)))
     }
 
     fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
@@ -50,43 +121,7 @@ impl<'de> Visitor<'de> for HumanNumberVisitor {
             ));
         }
 
-        // Using `bytes` instead of `chars` here because `split_at` takes a byte offset
-        match v
-            .bytes()
-            .map(|byte| byte as char)
-            .position(|ch| !(ch.is_numeric() || ch == '.'))
-        {
-            Some(unit_index) => {
-                let (value, unit) = v.split_at(unit_index);
-                let value: f64 = value.parse().map_err(Error::custom)?;
-
-                let multiple: u64 = match unit.trim_start() {
-                    "KB" | "kB" => 1000,
-                    "MB" => 1000 * 1000,
-                    "GB" => 1000 * 1000 * 1000,
-                    "TB" => 1000 * 1000 * 1000 * 1000,
-
-                    "KiB" => 1024,
-                    "MiB" => 1024 * 1024,
-                    "GiB" => 1024 * 1024 * 1024,
-                    "TiB" => 1024 * 1024 * 1024 * 1024,
-
-                    unit => {
-                        return Err(Error::invalid_value(
-                            Unexpected::Str(unit),
-                            &"known unit of measurement",
-                        ))
-                    }
-                };
-                let value = value * (multiple as f64);
-
-                Ok(Some(value.round() as u64))
-            }
-            None => {
-                // No unit found, interpret as raw number
-                v.parse().map(Some).map_err(Error::custom)
-            }
-        }
+        Parser::new(v).parse().map(Some)
     }
 
     fn visit_none<E>(self) -> Result<Self::Value, E>
@@ -104,19 +139,201 @@ impl<'de> Visitor<'de> for HumanNumberVisitor {
     }
 }
 
+/// Recursive descent parser of expressions, with `*` and `/` binding tighter than `+` and `-`.
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    /// Set while the implicit [`Expr::Current`] of a relative expression is still to be read.
+    relative: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Parser {
+            input,
+            pos: 0,
+            relative: input.starts_with(['+', '-', '*', '/']),
+        }
+    }
+
+    fn parse<E: Error>(mut self) -> Result<Expr, E> {
+        let expr = self.expr()?;
+
+        self.skip_whitespace();
+        match self.peek() {
+            None => Ok(expr),
+            Some(ch) => Err(self.unexpected(ch)),
+        }
+    }
+
+    fn expr<E: Error>(&mut self) -> Result<Expr, E> {
+        let mut lhs = self.term()?;
+
+        loop {
+            let op = match self.peek_operator() {
+                Some('+') => Op::Add,
+                Some('-') => Op::Sub,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.term()?));
+        }
+    }
+
+    fn term<E: Error>(&mut self) -> Result<Expr, E> {
+        let mut lhs = self.factor()?;
+
+        loop {
+            let op = match self.peek_operator() {
+                Some('*') => Op::Mul,
+                Some('/') => Op::Div,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+
+            lhs = Expr::Binary(Box::new(lhs), op, Box::new(self.factor()?));
+        }
+    }
+
+    fn factor<E: Error>(&mut self) -> Result<Expr, E> {
+        if self.relative {
+            self.relative = false;
+
+            return Ok(Expr::Current);
+        }
+
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+
+                let expr = self.expr()?;
+
+                self.skip_whitespace();
+                match self.peek() {
+                    Some(')') => {
+                        self.pos += 1;
+
+                        Ok(expr)
+                    }
+                    Some(ch) => Err(self.unexpected(ch)),
+                    None => Err(Error::custom("missing closing parenthesis")),
+                }
+            }
+            Some(ch) if ch.is_ascii_digit() || ch == '.' => self.number(),
+            Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => {
+                let name = self.take_while(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+
+                Ok(Expr::Field(name.to_string()))
+            }
+            Some(ch) => Err(self.unexpected(ch)),
+            None => Err(Error::custom("unexpected end of expression")),
+        }
+    }
+
+    fn number<E: Error>(&mut self) -> Result<Expr, E> {
+        let rest = &self.input[self.pos..];
+
+        if let Some(hex) = rest.strip_prefix("0x").or_else(|| rest.strip_prefix("0X")) {
+            let digits = hex
+                .find(|ch: char| !ch.is_ascii_hexdigit())
+                .unwrap_or(hex.len());
+            let value = u64::from_str_radix(&hex[..digits], 16).map_err(Error::custom)?;
+
+            self.pos += 2 + digits;
+
+            return Ok(Expr::Number(value));
+        }
+
+        let value = self.take_while(|ch| ch.is_ascii_digit() || ch == '.');
+
+        // A word following a number is its unit.
+        let start = self.pos;
+        self.skip_whitespace();
+        let unit = self.take_while(|ch| ch.is_ascii_alphabetic());
+
+        if unit.is_empty() {
+            self.pos = start;
+
+            return value.parse().map(Expr::Number).map_err(Error::custom);
+        }
+
+        let value: f64 = value.parse().map_err(Error::custom)?;
+
+        let multiple: u64 = match unit {
+            "KB" | "kB" => 1000,
+            "MB" => 1000 * 1000,
+            "GB" => 1000 * 1000 * 1000,
+            "TB" => 1000 * 1000 * 1000 * 1000,
+
+            "KiB" => 1024,
+            "MiB" => 1024 * 1024,
+            "GiB" => 1024 * 1024 * 1024,
+            "TiB" => 1024 * 1024 * 1024 * 1024,
+
+            unit => {
+                return Err(Error::invalid_value(
+                    Unexpected::Str(unit),
+                    &"known unit of measurement",
+                ))
+            }
+        };
+        let value = (value * (multiple as f64)).round();
+
+        if value >= u64::MAX as f64 {
+            return Err(Error::custom("number too large"));
+        }
+
+        Ok(Expr::Number(value as u64))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn peek_operator(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.peek().filter(|ch| matches!(ch, '+' | '-' | '*' | '/'))
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let rest = &self.input[self.pos..];
+        let len = rest.find(|ch| !f(ch)).unwrap_or(rest.len());
+
+        self.pos += len;
+
+        &rest[..len]
+    }
+
+    fn unexpected<E: Error>(&self, ch: char) -> E {
+        Error::custom(format_args!(
+            "unexpected '{}' at position {} of '{}'",
+            ch,
+            self.pos + 1,
+            self.input
+        ))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use serde::de::value::Error;
     use serde::de::IntoDeserializer;
 
-    use super::deserialize;
+    use super::{deserialize, EvalError, Expr, Op};
 
     #[test]
     fn test_deserialize() {
         fn check_result(input: &str, value: u64) {
             assert_eq!(
                 deserialize(input.into_deserializer()),
-                Ok::<_, Error>(Some(value))
+                Ok::<_, Error>(Some(Expr::Number(value)))
             );
         }
 
@@ -147,4 +364,54 @@ mod test {
         check_result("1234 GiB", 1234 * 1024 * 1024 * 1024);
         check_result("1234 TiB", 1234 * 1024 * 1024 * 1024 * 1024);
     }
+
+    #[test]
+    fn test_expressions() {
+        fn parse(input: &str) -> Expr {
+            deserialize::<serde::de::value::StrDeserializer<Error>>(input.into_deserializer())
+                .unwrap()
+                .unwrap()
+        }
+        fn eval(input: &str, current: u64) -> Result<u64, EvalError> {
+            parse(input).eval(current, &|name| match name {
+                "display_width" => Some(1920),
+                "display_height" => Some(1080),
+                _ => None,
+            })
+        }
+
+        assert_eq!(
+            parse("+512MiB"),
+            Expr::Binary(
+                Box::new(Expr::Current),
+                Op::Add,
+                Box::new(Expr::Number(512 * 1024 * 1024))
+            )
+        );
+        assert_eq!(eval("+512MiB", 1024), Ok(1024 + 512 * 1024 * 1024));
+        assert_eq!(eval("*2", 3), Ok(6));
+        assert_eq!(eval("*2 + 1", 3), Ok(7));
+        assert_eq!(eval("/ 2", 7), Ok(3));
+        assert_eq!(eval("display_width*display_height", 0), Ok(1920 * 1080));
+        assert_eq!(eval("(display_width + 80) * 2", 0), Ok(4000));
+        assert_eq!(eval("0x1E30", 0), Ok(0x1e30));
+        assert_eq!(eval("1.5 GiB", 0), Ok(1536 * 1024 * 1024));
+
+        assert_eq!(eval("-2", 1), Err(EvalError::Underflow));
+        assert_eq!(eval("*2", u64::MAX), Err(EvalError::Overflow));
+        assert_eq!(eval("/0", 1), Err(EvalError::DivisionByZero));
+        assert_eq!(
+            eval("display_depth", 0),
+            Err(EvalError::UnknownField("display_depth".to_string()))
+        );
+
+        for input in &["1 +", "(1", "1)", "2 apples", "1.5", "1 $ 2"] {
+            assert!(
+                deserialize::<serde::de::value::StrDeserializer<Error>>(input.into_deserializer())
+                    .is_err(),
+                "{}",
+                input
+            );
+        }
+    }
 }
@@ -0,0 +1,607 @@
+// SPDX-License-Identifier: MIT
+
+//! Loading of a TOML configuration file together with its drop-in directory.
+//!
+//! For a file such as `/etc/vgpu_unlock/config.toml` the drop-in directory is
+//! `/etc/vgpu_unlock/config.d`. The main file is read first, followed by every `*.toml` file in
+//! the drop-in directory in lexical order of the file name. Later files take precedence:
+//!
+//! - tables are merged key by key, recursively
+//! - arrays of tables (`[[...]]`) are appended
+//! - every other value, including plain arrays, replaces the earlier value
+//!
+//! Each file can contain sections that only apply to one process under `[process.<name>]`, where
+//! the name is the one from `/proc/self/comm` or the file name of `/proc/self/exe`. If there is
+//! no section for the current process, `[process.default]` is used instead. The section is merged
+//! on top of the rest of the file it is in before that file is merged with the other files.
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+use crate::log::{error, info};
+use crate::migrate::{self, VersionError};
+use crate::permissions::{self, Insecure, StrictModes};
+use crate::schema::{Schema, PROCESS_TABLE};
+use crate::utils;
+use crate::validate::{self, InvalidKey, Strictness, UnknownKey};
+
+/// Section of [`PROCESS_TABLE`] used when there is none for the current process.
+const DEFAULT_PROCESS_SECTION: &str = "default";
+
+/// Where the value of a key came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    File(PathBuf),
+    Env(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::File(path) => write!(f, "'{}'", path.display()),
+            Source::Env(name) => write!(f, "environment variable {}", name),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Layered {
+    pub table: Table,
+    /// Maps the dotted path of every leaf key to where it was set.
+    pub sources: BTreeMap<String, Source>,
+    /// The files merged with their per-process sections kept, so the result can be loaded again
+    /// by any process.
+    pub files: Table,
+}
+
+impl Layered {
+    /// Logs where each key of the merged configuration was set.
+    pub fn log_sources(&self) {
+        for (key, source) in &self.sources {
+            info!("Config key {} set by {}", key, source);
+        }
+    }
+}
+
+pub enum LoadError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    UnknownKeys(PathBuf, Vec<UnknownKey>),
+    InvalidKeys(PathBuf, Vec<InvalidKey>),
+    Insecure(PathBuf, Insecure),
+    Version(PathBuf, VersionError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Read(path, e) => write!(f, "Failed to read '{}': {}", path.display(), e),
+            LoadError::Parse(path, e) => write!(f, "Failed to parse '{}': {}", path.display(), e),
+            LoadError::UnknownKeys(path, unknown_keys) => {
+                write!(f, "Unknown keys in '{}':", path.display())?;
+
+                for unknown_key in unknown_keys {
+                    write!(f, " {};", unknown_key)?;
+                }
+
+                Ok(())
+            }
+            LoadError::InvalidKeys(path, invalid_keys) => {
+                write!(f, "Invalid keys in '{}':", path.display())?;
+
+                for invalid_key in invalid_keys {
+                    write!(f, " {};", invalid_key)?;
+                }
+
+                Ok(())
+            }
+            LoadError::Insecure(path, e) => {
+                write!(f, "Refusing to load '{}': {}", path.display(), e)
+            }
+            LoadError::Version(path, e) => write!(f, "Failed to load '{}': {}", path.display(), e),
+        }
+    }
+}
+
+/// Returns the drop-in directory for `path`, e.g. `config.d` for `config.toml`.
+pub fn drop_in_dir(path: &Path) -> PathBuf {
+    path.with_extension("d")
+}
+
+/// Returns the files making up the configuration at `path` in the order they are merged. Files
+/// that do not exist are left out.
+pub fn files(path: &Path) -> Result<Vec<PathBuf>, LoadError> {
+    let mut files = Vec::new();
+
+    if path.is_file() {
+        files.push(path.to_path_buf());
+    }
+
+    let dir = drop_in_dir(path);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(files),
+        Err(e) => return Err(LoadError::Read(dir, e)),
+    };
+
+    let mut drop_ins = Vec::new();
+
+    for entry in entries {
+        let entry = entry.map_err(|e| LoadError::Read(dir.clone(), e))?;
+        let path = entry.path();
+
+        if path.extension() == Some(OsStr::new("toml")) && path.is_file() {
+            drop_ins.push(path);
+        }
+    }
+
+    drop_ins.sort();
+    files.extend(drop_ins);
+
+    Ok(files)
+}
+
+/// Identifies the state of a file on disk so changes can be detected without reading it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileStamp {
+    path: PathBuf,
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: (i64, i64),
+}
+
+/// Returns a stamp for every file making up the configuration at `path`. The configuration
+/// needs to be loaded again when the result differs from an earlier call.
+pub fn stamps(path: &Path) -> Result<Vec<FileStamp>, LoadError> {
+    files(path)?
+        .into_iter()
+        .map(|path| match fs::metadata(&path) {
+            Ok(metadata) => Ok(FileStamp {
+                dev: metadata.dev(),
+                ino: metadata.ino(),
+                size: metadata.size(),
+                mtime: (metadata.mtime(), metadata.mtime_nsec()),
+                path,
+            }),
+            Err(e) => Err(LoadError::Read(path, e)),
+        })
+        .collect()
+}
+
+/// Loads and merges the configuration at `path` and its drop-in directory.
+///
+/// Every file is checked for keys not allowed by `schema`. Unknown keys and keys that do not have
+/// the format of their table are logged, or cause the load to fail with [`Strictness::Strict`].
+/// Keys renamed since the version a file declares are logged and migrated to their current name.
+/// The ownership and mode of every file is checked according to `strict_modes` before it is read.
+///
+/// Returns `Ok(None)` when neither the file nor any drop-in exists.
+pub fn load(
+    path: &Path,
+    schema: &Schema,
+    strictness: Strictness,
+    strict_modes: StrictModes,
+) -> Result<Option<Layered>, LoadError> {
+    let files = files(path)?;
+
+    if files.is_empty() {
+        return Ok(None);
+    }
+
+    let process_names = utils::process_names();
+    let mut layered = Layered::default();
+
+    for file in files {
+        if let Err(e) = permissions::enforce(&file, strict_modes) {
+            return Err(LoadError::Insecure(file, e));
+        }
+
+        let data = fs::read_to_string(&file).map_err(|e| LoadError::Read(file.clone(), e))?;
+        let mut table: Table =
+            toml::from_str(&data).map_err(|e| LoadError::Parse(file.clone(), e))?;
+        let version = migrate::version(&table).map_err(|e| LoadError::Version(file.clone(), e))?;
+        let findings = validate::check(&data, schema);
+
+        if !findings.unknown.is_empty() {
+            if strictness == Strictness::Strict {
+                return Err(LoadError::UnknownKeys(file, findings.unknown));
+            }
+
+            for unknown_key in findings.unknown {
+                error!("'{}' {}, ignoring it", file.display(), unknown_key);
+            }
+        }
+        if !findings.invalid.is_empty() {
+            if strictness == Strictness::Strict {
+                return Err(LoadError::InvalidKeys(file, findings.invalid));
+            }
+
+            for invalid_key in findings.invalid {
+                error!("'{}' {}, ignoring it", file.display(), invalid_key);
+            }
+        }
+        for deprecated_key in findings.deprecated {
+            error!("'{}' {}", file.display(), deprecated_key);
+        }
+
+        migrate::migrate(&mut table, schema, version);
+        table.remove(migrate::VERSION_KEY);
+
+        let source = Source::File(file);
+
+        normalize_keys(&mut table, schema, &source);
+
+        merge_table(
+            &mut layered.files,
+            table.clone(),
+            &source,
+            "",
+            &mut BTreeMap::new(),
+        );
+        apply_process_section(&mut table, &process_names, &source);
+        merge(&mut layered, table, &source);
+    }
+
+    Ok(Some(layered))
+}
+
+/// Loads `table`, the [`Layered::files`] of an earlier load read from `source`, applying the
+/// per-process section for the current process again.
+pub fn load_files(mut table: Table, source: &Source) -> Layered {
+    let mut layered = Layered {
+        files: table.clone(),
+        ..Default::default()
+    };
+
+    apply_process_section(&mut table, &utils::process_names(), source);
+    merge(&mut layered, table, source);
+
+    layered
+}
+
+/// Rewrites the keys of the [`Schema::Keyed`] tables of `table` to their normal form, so a key
+/// written differently in several places is merged as one.
+fn normalize_keys(table: &mut Table, schema: &Schema, source: &Source) {
+    match schema {
+        Schema::Struct(_, nested) => {
+            for (name, schema) in nested.iter() {
+                if let Some(value) = table.get_mut(*name) {
+                    normalize_value(value, schema, source);
+                }
+            }
+        }
+        Schema::Map(schema) => {
+            for (_, value) in table.iter_mut() {
+                normalize_value(value, schema, source);
+            }
+        }
+        Schema::Keyed(keys, schema) => {
+            for (key, mut value) in mem::take(table) {
+                normalize_value(&mut value, schema, source);
+
+                let key = validate::normal_key(*keys, &key);
+
+                match (table.get_mut(&key), value) {
+                    (Some(Value::Table(existing)), Value::Table(value)) => {
+                        // The sources are recorded when the whole file is merged afterwards.
+                        merge_table(existing, value, source, "", &mut BTreeMap::new());
+                    }
+                    (_, value) => {
+                        table.insert(key, value);
+                    }
+                }
+            }
+        }
+        Schema::Array(_) => {}
+        Schema::Renamed(_, schema) | Schema::Restricted(_, _, schema) => {
+            normalize_keys(table, schema, source)
+        }
+    }
+}
+
+fn normalize_value(value: &mut Value, schema: &Schema, source: &Source) {
+    match (value, schema) {
+        (Value::Array(array), Schema::Array(schema)) => {
+            for value in array {
+                if let Value::Table(table) = value {
+                    normalize_keys(table, schema, source);
+                }
+            }
+        }
+        (Value::Table(table), schema) => normalize_keys(table, schema, source),
+        _ => {}
+    }
+}
+
+/// Removes the per-process sections from `table` and merges the one for the process known by
+/// one of `process_names`, or the default section, on top of the rest of `table`.
+fn apply_process_section(table: &mut Table, process_names: &[String], source: &Source) {
+    let mut sections = match table.remove(PROCESS_TABLE) {
+        Some(Value::Table(sections)) => sections,
+        _ => return,
+    };
+
+    let name = process_names
+        .iter()
+        .find(|name| sections.contains_key(name.as_str()))
+        .map_or(DEFAULT_PROCESS_SECTION, String::as_str);
+
+    if let Some(Value::Table(section)) = sections.remove(name) {
+        // The sources are recorded when the whole file is merged afterwards.
+        merge_table(table, section, source, "", &mut BTreeMap::new());
+    }
+}
+
+/// Merges `table` read from `source` on top of `layered`.
+pub fn merge(layered: &mut Layered, table: Table, source: &Source) {
+    merge_table(&mut layered.table, table, source, "", &mut layered.sources);
+}
+
+fn merge_table(
+    into: &mut Table,
+    from: Table,
+    source: &Source,
+    prefix: &str,
+    sources: &mut BTreeMap<String, Source>,
+) {
+    for (key, value) in from {
+        let path = key_path(prefix, &key);
+
+        match (into.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => {
+                merge_table(existing, table, source, &path, sources);
+            }
+            (Some(Value::Array(existing)), Value::Array(array))
+                if is_array_of_tables(existing) && is_array_of_tables(&array) =>
+            {
+                let offset = existing.len();
+
+                for (i, value) in array.into_iter().enumerate() {
+                    record_sources(
+                        &value,
+                        source,
+                        &format!("{}[{}]", path, offset + i),
+                        sources,
+                    );
+                    existing.push(value);
+                }
+            }
+            (_, value) => {
+                // Drop any record of keys below the value being replaced.
+                let nested = format!("{}.", path);
+                let indexed = format!("{}[", path);
+                sources.retain(|k, _| !k.starts_with(&nested) && !k.starts_with(&indexed));
+
+                record_sources(&value, source, &path, sources);
+                into.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Returns the leaf values of `table` keyed by their dotted path.
+pub fn flatten(table: &Table) -> BTreeMap<String, &Value> {
+    fn flatten_value<'a>(value: &'a Value, path: String, out: &mut BTreeMap<String, &'a Value>) {
+        match value {
+            Value::Table(table) => {
+                for (key, value) in table {
+                    flatten_value(value, key_path(&path, key), out);
+                }
+            }
+            Value::Array(array) if is_array_of_tables(array) => {
+                for (i, value) in array.iter().enumerate() {
+                    flatten_value(value, format!("{}[{}]", path, i), out);
+                }
+            }
+            _ => {
+                out.insert(path, value);
+            }
+        }
+    }
+
+    let mut out = BTreeMap::new();
+
+    for (key, value) in table {
+        flatten_value(value, key_path("", key), &mut out);
+    }
+
+    out
+}
+
+/// Describes every leaf key that was added, removed or changed between `old` and `new`.
+pub fn diff(old: &Table, new: &Table) -> Vec<String> {
+    let old = flatten(old);
+    let new = flatten(new);
+    let mut changes = Vec::new();
+
+    for (key, old_value) in &old {
+        match new.get(key) {
+            Some(new_value) if new_value != old_value => {
+                changes.push(format!("{}: {} -> {}", key, old_value, new_value));
+            }
+            Some(_) => {}
+            None => changes.push(format!("{}: {} -> (removed)", key, old_value)),
+        }
+    }
+    for (key, new_value) in &new {
+        if !old.contains_key(key) {
+            changes.push(format!("{}: (unset) -> {}", key, new_value));
+        }
+    }
+
+    changes
+}
+
+fn record_sources(
+    value: &Value,
+    source: &Source,
+    path: &str,
+    sources: &mut BTreeMap<String, Source>,
+) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                record_sources(value, source, &key_path(path, key), sources);
+            }
+        }
+        Value::Array(array) if is_array_of_tables(array) => {
+            for (i, value) in array.iter().enumerate() {
+                record_sources(value, source, &format!("{}[{}]", path, i), sources);
+            }
+        }
+        _ => {
+            sources.insert(path.to_string(), source.clone());
+        }
+    }
+}
+
+fn is_array_of_tables(array: &[Value]) -> bool {
+    !array.is_empty() && array.iter().all(Value::is_table)
+}
+
+/// Appends `key` to the dotted key path `prefix`, quoting it when it is not a bare key.
+pub fn key_path(prefix: &str, key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+
+    match (prefix.is_empty(), bare) {
+        (true, true) => key.to_string(),
+        (true, false) => format!("{:?}", key),
+        (false, true) => format!("{}.{}", prefix, key),
+        (false, false) => format!("{}.{:?}", prefix, key),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use toml::Table;
+
+    use super::{apply_process_section, diff, key_path, merge, Layered, Source};
+
+    fn file(name: &str) -> Source {
+        Source::File(PathBuf::from(name))
+    }
+
+    fn merged(files: &[(&str, &str)]) -> Layered {
+        let mut layered = Layered::default();
+
+        for (name, data) in files {
+            merge(&mut layered, toml::from_str(data).unwrap(), &file(name));
+        }
+
+        layered
+    }
+
+    #[test]
+    fn test_merge() {
+        let layered = merged(&[
+            (
+                "config.toml",
+                "unlock = true\n[pci_info_map.0x1e84]\ndevice_id = 1\nsub_system_id = 2\n",
+            ),
+            (
+                "config.d/10-a.toml",
+                "unlock = false\n[pci_info_map.0x1e84]\ndevice_id = 3\n",
+            ),
+        ]);
+
+        assert_eq!(
+            layered.table.to_string(),
+            "unlock = false\n\n[pci_info_map.0x1e84]\ndevice_id = 3\nsub_system_id = 2\n"
+        );
+        assert_eq!(layered.sources["unlock"], file("config.d/10-a.toml"));
+        assert_eq!(
+            layered.sources["pci_info_map.0x1e84.device_id"],
+            file("config.d/10-a.toml")
+        );
+        assert_eq!(
+            layered.sources["pci_info_map.0x1e84.sub_system_id"],
+            file("config.toml")
+        );
+    }
+
+    #[test]
+    fn test_key_path() {
+        let path = key_path(&key_path("profile", "GRID P40-2A.x"), "num_displays");
+
+        assert_eq!(path, "profile.\"GRID P40-2A.x\".num_displays");
+    }
+
+    #[test]
+    fn test_merge_appends_arrays_of_tables() {
+        let layered = merged(&[
+            ("a.toml", "[[rule]]\nstop = true\n"),
+            ("b.toml", "[[rule]]\nstop = false\n"),
+        ]);
+
+        assert_eq!(layered.table["rule"].as_array().unwrap().len(), 2);
+        assert_eq!(layered.sources["rule[0].stop"], file("a.toml"));
+        assert_eq!(layered.sources["rule[1].stop"], file("b.toml"));
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = toml::from_str("unlock = true\nunlock_migration = false\n").unwrap();
+        let new = toml::from_str(
+            "unlock = false\n[pci_info_map.0x1e84]\ndevice_id = 1\nsub_system_id = 2\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                "unlock: true -> false",
+                "unlock_migration: false -> (removed)",
+                "pci_info_map.0x1e84.device_id: (unset) -> 1",
+                "pci_info_map.0x1e84.sub_system_id: (unset) -> 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_process_section() {
+        let data = "unlock = true\nunlock_migration = false\n\
+                    [process.nvidia-vgpud]\nunlock = false\n\
+                    [process.default]\nunlock_migration = true\n";
+        let names = |names: &[&str]| names.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        let mut table: Table = toml::from_str(data).unwrap();
+        apply_process_section(&mut table, &names(&["nvidia-vgpud"]), &file("a.toml"));
+        assert_eq!(
+            table.to_string(),
+            "unlock = false\nunlock_migration = false\n"
+        );
+
+        let mut table: Table = toml::from_str(data).unwrap();
+        apply_process_section(&mut table, &names(&["nvidia-vgpu-mgr"]), &file("a.toml"));
+        assert_eq!(
+            table.to_string(),
+            "unlock = true\nunlock_migration = true\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_replaces_values() {
+        let layered = merged(&[
+            ("a.toml", "[profile.nvidia-55]\nnum_displays = 1\n"),
+            ("b.toml", "profile = 1\n"),
+        ]);
+
+        assert_eq!(layered.table.to_string(), "profile = 1\n");
+        assert_eq!(layered.sources.len(), 1);
+        assert_eq!(layered.sources["profile"], file("b.toml"));
+    }
+}
@@ -8,37 +8,42 @@
 //! - Arc Compute for their work on Mdev-GPU and GVM documenting more field names in the vGPU
 //!   configuration structure
 
-use std::cmp;
-use std::collections::HashMap;
 use std::env;
-use std::fs;
-use std::io::{ErrorKind, Write};
 use std::mem;
 use std::os::raw::{c_int, c_ulong, c_void};
 use std::os::unix::io::RawFd;
 use std::path::PathBuf;
-use std::process;
 use std::str;
 
 use ctor::ctor;
 use libc::RTLD_NEXT;
 use parking_lot::Mutex;
-use serde::Deserialize;
+use vgpu_unlock_schema::{migrate, schema};
 
 mod config;
+mod consistency;
 mod dump;
+mod explain;
+mod extra_params;
 mod format;
 mod human_number;
 mod ioctl;
+mod layered;
 mod log;
 mod nvidia;
+mod overlay;
+mod overrides;
+mod pci;
+mod permissions;
+mod resolution;
 mod string_number;
 mod to_bytes;
 mod utils;
 mod uuid;
+mod validate;
 
-use crate::config::Config;
-use crate::format::WideCharFormat;
+use crate::config::{FailurePolicy, SharedConfig, LAST_KNOWN_GOOD_PATH};
+use crate::extra_params::ExtraParams;
 use crate::log::{error, info};
 use crate::nvidia::ctrl0000vgpu::{
     Nv0000CtrlVgpuCreateDeviceParams, Nv0000CtrlVgpuGetStartDataParams,
@@ -65,42 +70,46 @@ use crate::nvidia::error::{
     NV_ERR_BUSY_RETRY, NV_ERR_NOT_SUPPORTED, NV_ERR_OBJECT_NOT_FOUND, NV_OK,
 };
 use crate::nvidia::nvos::{Nvos54Parameters, NV_ESC_RM_CONTROL};
+use crate::pci::{PciBdf, PhysicalGpu};
 use crate::string_number::U32;
-#[cfg(feature = "proxmox")]
-use crate::utils::uuid_to_vmid;
 use crate::uuid::Uuid;
 
 static LAST_MDEV_UUID: Mutex<Option<Uuid>> = parking_lot::const_mutex(None);
+static LAST_GPU: Mutex<Option<PhysicalGpu>> = parking_lot::const_mutex(None);
 
 #[ctor]
-static CONFIG: Config = {
-    match fs::read_to_string(DEFAULT_CONFIG_PATH) {
-        Ok(config) => match toml::from_str::<Config>(&config) {
-            Ok(config) => {
-                println!("{:#x?}", config);
+static CONFIG: SharedConfig = {
+    let config_path = match env::var_os("VGPU_UNLOCK_CONFIG_PATH") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(DEFAULT_CONFIG_PATH),
+    };
 
-                config
-            }
-            Err(e) => {
-                eprintln!("Failed to decode config: {}", e);
+    info!(
+        "Loading config from '{}', then '{}/*.toml', then VGPU_UNLOCK_* environment variables, \
+         later sources take precedence",
+        config_path.display(),
+        layered::drop_in_dir(&config_path).display()
+    );
 
-                process::abort();
-            }
-        },
-        Err(e) => {
-            if e.kind() != ErrorKind::NotFound {
-                eprintln!("Failed to read config: {}", e);
-            }
+    let last_known_good_path = match env::var_os("VGPU_UNLOCK_LAST_KNOWN_GOOD_PATH") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(LAST_KNOWN_GOOD_PATH),
+    };
 
-            Default::default()
-        }
-    }
+    SharedConfig::load(
+        &config_path,
+        &last_known_good_path,
+        FailurePolicy::from_env(),
+    )
 };
 
 const DEFAULT_CONFIG_PATH: &str = "/etc/vgpu_unlock/config.toml";
-const DEFAULT_PROFILE_OVERRIDE_CONFIG_PATH: &str = "/etc/vgpu_unlock/profile_override.toml";
 
-trait VgpuConfigLike {
+trait VgpuConfigLike: Clone {
+    /// Whether the profiles of this layout are listed for every vGPU type of a GPU, which
+    /// `nvidia-vgpud` does.
+    const LISTS_TYPES: bool;
+
     fn vgpu_type(&mut self) -> &mut u32;
     fn vgpu_name(&mut self) -> &mut [u8];
     fn vgpu_class(&mut self) -> &mut [u8];
@@ -118,8 +127,10 @@ trait VgpuConfigLike {
     fn multi_vgpu_supported(&mut self) -> &mut u32;
     fn vdev_id(&mut self) -> &mut u64;
     fn pdev_id(&mut self) -> &mut u64;
-    //fn profile_size(&mut self) -> Option<&mut u64>;
+    // The fields returning an `Option` are only present in some layouts.
+    fn profile_size(&mut self) -> Option<&mut u64>;
     fn fb_length(&mut self) -> &mut u64;
+    fn gsp_heap_size(&mut self) -> Option<&mut u64>;
     fn mappable_video_size(&mut self) -> &mut u64;
     fn fb_reservation(&mut self) -> &mut u64;
     fn encoder_capacity(&mut self) -> &mut u32;
@@ -129,7 +140,19 @@ trait VgpuConfigLike {
     fn adapter_name_unicode(&mut self) -> &mut [u16; 64];
     fn short_gpu_name_string(&mut self) -> &mut [u8; 64];
     fn licensed_product_name(&mut self) -> &mut [u8; 128];
-    //fn vgpu_extra_params(&mut self) -> &mut [u8];
+    fn vgpu_extra_params(&mut self) -> ExtraParams<'_>;
+    fn ftrace_enable(&mut self) -> Option<&mut u32>;
+    fn gpu_direct_supported(&mut self) -> Option<&mut u32>;
+    fn nvlink_p2p_supported(&mut self) -> Option<&mut u32>;
+    fn max_instance_per_gi(&mut self) -> Option<&mut u32>;
+    fn multi_vgpu_exclusive(&mut self) -> Option<&mut u32>;
+    fn exclusive_type(&mut self) -> Option<&mut u32>;
+    fn exclusive_size(&mut self) -> Option<&mut u32>;
+    fn gpu_instance_profile_id(&mut self) -> Option<&mut u32>;
+    fn placement_size(&mut self) -> Option<&mut u32>;
+    // The placement tables are returned along with the number of entries in use.
+    fn homogeneous_placement_ids(&mut self) -> Option<(&mut u32, &mut [u32])>;
+    fn heterogeneous_placement_ids(&mut self) -> Option<(&mut u32, &mut [u32])>;
 }
 
 macro_rules! impl_trait_fn {
@@ -158,6 +181,8 @@ macro_rules! impl_trait_fn_aligned {
 }
 
 impl VgpuConfigLike for NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 {
+    const LISTS_TYPES: bool = false;
+
     impl_trait_fn!(vgpu_type, u32);
     impl_trait_fn!(vgpu_name, [u8; ..]);
     impl_trait_fn!(vgpu_class, [u8; ..]);
@@ -176,13 +201,16 @@ impl VgpuConfigLike for NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 {
     impl_trait_fn!(vdev_id, u64);
     impl_trait_fn!(pdev_id, u64);
 
-    /*
     fn profile_size(&mut self) -> Option<&mut u64> {
         None
     }
-    */
 
     impl_trait_fn!(fb_length, u64);
+
+    fn gsp_heap_size(&mut self) -> Option<&mut u64> {
+        None
+    }
+
     impl_trait_fn!(mappable_video_size, u64);
     impl_trait_fn!(fb_reservation, u64);
     impl_trait_fn!(encoder_capacity, u32);
@@ -192,10 +220,59 @@ impl VgpuConfigLike for NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 {
     impl_trait_fn!(adapter_name_unicode, [u16; 64]);
     impl_trait_fn!(short_gpu_name_string, [u8; 64]);
     impl_trait_fn!(licensed_product_name, [u8; 128]);
-    //impl_trait_fn!(vgpu_extra_params, [u8]);
+
+    fn vgpu_extra_params(&mut self) -> ExtraParams<'_> {
+        ExtraParams::Bytes(&mut self.vgpu_extra_params[..])
+    }
+
+    fn ftrace_enable(&mut self) -> Option<&mut u32> {
+        None
+    }
+
+    fn gpu_direct_supported(&mut self) -> Option<&mut u32> {
+        None
+    }
+
+    fn nvlink_p2p_supported(&mut self) -> Option<&mut u32> {
+        None
+    }
+
+    fn max_instance_per_gi(&mut self) -> Option<&mut u32> {
+        None
+    }
+
+    fn multi_vgpu_exclusive(&mut self) -> Option<&mut u32> {
+        None
+    }
+
+    fn exclusive_type(&mut self) -> Option<&mut u32> {
+        None
+    }
+
+    fn exclusive_size(&mut self) -> Option<&mut u32> {
+        None
+    }
+
+    fn gpu_instance_profile_id(&mut self) -> Option<&mut u32> {
+        None
+    }
+
+    fn placement_size(&mut self) -> Option<&mut u32> {
+        None
+    }
+
+    fn homogeneous_placement_ids(&mut self) -> Option<(&mut u32, &mut [u32])> {
+        None
+    }
+
+    fn heterogeneous_placement_ids(&mut self) -> Option<(&mut u32, &mut [u32])> {
+        None
+    }
 }
 
 impl VgpuConfigLike for NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV580 {
+    const LISTS_TYPES: bool = false;
+
     impl_trait_fn!(vgpu_type, u32);
     impl_trait_fn!(vgpu_name, [u8; ..]);
     impl_trait_fn!(vgpu_class, [u8; ..]);
@@ -214,13 +291,16 @@ impl VgpuConfigLike for NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV580 {
     impl_trait_fn!(vdev_id, u64);
     impl_trait_fn!(pdev_id, u64);
 
-    /*
     fn profile_size(&mut self) -> Option<&mut u64> {
         None
     }
-    */
 
     impl_trait_fn!(fb_length, u64);
+
+    fn gsp_heap_size(&mut self) -> Option<&mut u64> {
+        None
+    }
+
     impl_trait_fn!(mappable_video_size, u64);
     impl_trait_fn!(fb_reservation, u64);
     impl_trait_fn!(encoder_capacity, u32);
@@ -230,10 +310,65 @@ impl VgpuConfigLike for NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV580 {
     impl_trait_fn!(adapter_name_unicode, [u16; 64]);
     impl_trait_fn!(short_gpu_name_string, [u8; 64]);
     impl_trait_fn!(licensed_product_name, [u8; 128]);
-    //impl_trait_fn!(vgpu_extra_params, [u8]);
+
+    fn vgpu_extra_params(&mut self) -> ExtraParams<'_> {
+        ExtraParams::Bytes(&mut self.vgpu_extra_params[..])
+    }
+
+    fn ftrace_enable(&mut self) -> Option<&mut u32> {
+        Some(&mut self.ftrace_enable)
+    }
+
+    fn gpu_direct_supported(&mut self) -> Option<&mut u32> {
+        Some(&mut self.gpu_direct_supported)
+    }
+
+    fn nvlink_p2p_supported(&mut self) -> Option<&mut u32> {
+        Some(&mut self.nvlink_p2p_supported)
+    }
+
+    fn max_instance_per_gi(&mut self) -> Option<&mut u32> {
+        Some(&mut self.max_instance_per_gi)
+    }
+
+    fn multi_vgpu_exclusive(&mut self) -> Option<&mut u32> {
+        Some(&mut self.multi_vgpu_exclusive)
+    }
+
+    fn exclusive_type(&mut self) -> Option<&mut u32> {
+        Some(&mut self.exclusive_type)
+    }
+
+    fn exclusive_size(&mut self) -> Option<&mut u32> {
+        Some(&mut self.exclusive_size)
+    }
+
+    fn gpu_instance_profile_id(&mut self) -> Option<&mut u32> {
+        Some(&mut self.gpu_instance_profile_id)
+    }
+
+    fn placement_size(&mut self) -> Option<&mut u32> {
+        Some(&mut self.placement_size)
+    }
+
+    fn homogeneous_placement_ids(&mut self) -> Option<(&mut u32, &mut [u32])> {
+        Some((
+            &mut self.homogeneous_placement_count,
+            &mut self.homogeneous_placement_ids[..],
+        ))
+    }
+
+    fn heterogeneous_placement_ids(&mut self) -> Option<(&mut u32, &mut [u32])> {
+        Some((
+            &mut self.heterogeneous_placement_count,
+            &mut self.heterogeneous_placement_ids[..],
+        ))
+    }
 }
 
 impl VgpuConfigLike for NvA081CtrlVgpuInfoV525 {
+    const LISTS_TYPES: bool = true;
+
     impl_trait_fn!(vgpu_type, u32);
     impl_trait_fn!(vgpu_name, [u8; ..]);
     impl_trait_fn!(vgpu_class, [u8; ..]);
@@ -252,13 +387,16 @@ impl VgpuConfigLike for NvA081CtrlVgpuInfoV525 {
     impl_trait_fn_aligned!(vdev_id, u64);
     impl_trait_fn_aligned!(pdev_id, u64);
 
-    /*
     fn profile_size(&mut self) -> Option<&mut u64> {
         Some(&mut self.profile_size.0)
     }
-    */
 
     impl_trait_fn_aligned!(fb_length, u64);
+
+    fn gsp_heap_size(&mut self) -> Option<&mut u64> {
+        Some(&mut self.gsp_heap_size.0)
+    }
+
     impl_trait_fn_aligned!(mappable_video_size, u64);
     impl_trait_fn_aligned!(fb_reservation, u64);
     impl_trait_fn!(encoder_capacity, u32);
@@ -268,10 +406,59 @@ impl VgpuConfigLike for NvA081CtrlVgpuInfoV525 {
     impl_trait_fn!(adapter_name_unicode, [u16; 64]);
     impl_trait_fn!(short_gpu_name_string, [u8; 64]);
     impl_trait_fn!(licensed_product_name, [u8; 128]);
-    //impl_trait_fn!(vgpu_extra_params, [u8]);
+
+    fn vgpu_extra_params(&mut self) -> ExtraParams<'_> {
+        ExtraParams::Words(&mut self.vgpu_extra_params[..])
+    }
+
+    fn ftrace_enable(&mut self) -> Option<&mut u32> {
+        Some(&mut self.ftrace_enable)
+    }
+
+    fn gpu_direct_supported(&mut self) -> Option<&mut u32> {
+        Some(&mut self.gpu_direct_supported)
+    }
+
+    fn nvlink_p2p_supported(&mut self) -> Option<&mut u32> {
+        Some(&mut self.nvlink_p2p_supported)
+    }
+
+    fn max_instance_per_gi(&mut self) -> Option<&mut u32> {
+        None
+    }
+
+    fn multi_vgpu_exclusive(&mut self) -> Option<&mut u32> {
+        Some(&mut self.multi_vgpu_exclusive)
+    }
+
+    fn exclusive_type(&mut self) -> Option<&mut u32> {
+        Some(&mut self.exclusive_type)
+    }
+
+    fn exclusive_size(&mut self) -> Option<&mut u32> {
+        Some(&mut self.exclusive_size)
+    }
+
+    fn gpu_instance_profile_id(&mut self) -> Option<&mut u32> {
+        Some(&mut self.gpu_instance_profile_id)
+    }
+
+    fn placement_size(&mut self) -> Option<&mut u32> {
+        None
+    }
+
+    fn homogeneous_placement_ids(&mut self) -> Option<(&mut u32, &mut [u32])> {
+        None
+    }
+
+    fn heterogeneous_placement_ids(&mut self) -> Option<(&mut u32, &mut [u32])> {
+        None
+    }
 }
 
 impl VgpuConfigLike for NvA081CtrlVgpuInfoV580 {
+    const LISTS_TYPES: bool = true;
+
     impl_trait_fn!(vgpu_type, u32);
     impl_trait_fn!(vgpu_name, [u8; ..]);
     impl_trait_fn!(vgpu_class, [u8; ..]);
@@ -290,13 +477,16 @@ impl VgpuConfigLike for NvA081CtrlVgpuInfoV580 {
     impl_trait_fn_aligned!(vdev_id, u64);
     impl_trait_fn_aligned!(pdev_id, u64);
 
-    /*
     fn profile_size(&mut self) -> Option<&mut u64> {
         Some(&mut self.profile_size.0)
     }
-    */
 
     impl_trait_fn_aligned!(fb_length, u64);
+
+    fn gsp_heap_size(&mut self) -> Option<&mut u64> {
+        Some(&mut self.gsp_heap_size.0)
+    }
+
     impl_trait_fn_aligned!(mappable_video_size, u64);
     impl_trait_fn_aligned!(fb_reservation, u64);
     impl_trait_fn!(encoder_capacity, u32);
@@ -306,50 +496,60 @@ impl VgpuConfigLike for NvA081CtrlVgpuInfoV580 {
     impl_trait_fn!(adapter_name_unicode, [u16; 64]);
     impl_trait_fn!(short_gpu_name_string, [u8; 64]);
     impl_trait_fn!(licensed_product_name, [u8; 128]);
-    //impl_trait_fn!(vgpu_extra_params, [u8]);
-}
 
-#[derive(Deserialize)]
-struct ProfileOverridesConfig {
-    #[serde(default)]
-    profile: HashMap<String, VgpuProfileOverride>,
-    #[serde(default)]
-    mdev: HashMap<String, VgpuProfileOverride>,
-    #[cfg(feature = "proxmox")]
-    #[serde(default)]
-    vm: HashMap<String, VgpuProfileOverride>,
-}
+    fn vgpu_extra_params(&mut self) -> ExtraParams<'_> {
+        ExtraParams::Words(&mut self.vgpu_extra_params[..])
+    }
+
+    fn ftrace_enable(&mut self) -> Option<&mut u32> {
+        Some(&mut self.ftrace_enable)
+    }
+
+    fn gpu_direct_supported(&mut self) -> Option<&mut u32> {
+        Some(&mut self.gpu_direct_supported)
+    }
+
+    fn nvlink_p2p_supported(&mut self) -> Option<&mut u32> {
+        Some(&mut self.nvlink_p2p_supported)
+    }
+
+    fn max_instance_per_gi(&mut self) -> Option<&mut u32> {
+        Some(&mut self.max_instance_per_gi)
+    }
+
+    fn multi_vgpu_exclusive(&mut self) -> Option<&mut u32> {
+        Some(&mut self.multi_vgpu_exclusive)
+    }
 
-#[derive(Deserialize)]
-struct VgpuProfileOverride {
-    gpu_type: Option<u32>,
-    card_name: Option<String>,
-    vgpu_type: Option<String>,
-    features: Option<String>,
-    max_instances: Option<u32>,
-    num_displays: Option<u32>,
-    display_width: Option<u32>,
-    display_height: Option<u32>,
-    max_pixels: Option<u32>,
-    frl_config: Option<u32>,
-    cuda_enabled: Option<u32>,
-    ecc_supported: Option<u32>,
-    mig_instance_size: Option<u32>,
-    multi_vgpu_supported: Option<u32>,
-    pci_id: Option<u64>,
-    pci_device_id: Option<u64>,
-    #[serde(default, with = "human_number")]
-    framebuffer: Option<u64>,
-    #[serde(default, with = "human_number")]
-    mappable_video_size: Option<u64>,
-    #[serde(default, with = "human_number")]
-    framebuffer_reservation: Option<u64>,
-    encoder_capacity: Option<u32>,
-    bar1_length: Option<u64>,
-    frl_enabled: Option<u32>,
-    adapter_name: Option<String>,
-    short_gpu_name: Option<String>,
-    license_type: Option<String>,
+    fn exclusive_type(&mut self) -> Option<&mut u32> {
+        Some(&mut self.exclusive_type)
+    }
+
+    fn exclusive_size(&mut self) -> Option<&mut u32> {
+        Some(&mut self.exclusive_size)
+    }
+
+    fn gpu_instance_profile_id(&mut self) -> Option<&mut u32> {
+        Some(&mut self.gpu_instance_profile_id)
+    }
+
+    fn placement_size(&mut self) -> Option<&mut u32> {
+        Some(&mut self.placement_size)
+    }
+
+    fn homogeneous_placement_ids(&mut self) -> Option<(&mut u32, &mut [u32])> {
+        Some((
+            &mut self.homogeneous_placement_count,
+            &mut self.homogeneous_placement_ids[..],
+        ))
+    }
+
+    fn heterogeneous_placement_ids(&mut self) -> Option<(&mut u32, &mut [u32])> {
+        Some((
+            &mut self.heterogeneous_placement_count,
+            &mut self.heterogeneous_placement_ids[..],
+        ))
+    }
 }
 
 fn check_size_log(name: &str, actual_size: usize, expected_size: usize) {
@@ -383,9 +583,7 @@ fn check_size_raw(actual_size: usize, expected_size: usize) -> bool {
 
 #[inline(always)]
 fn check_size_raw_multiple(actual_size: usize, expected_size: &[usize]) -> bool {
-    expected_size
-        .iter()
-        .any(|&expected_size| actual_size == expected_size)
+    expected_size.contains(&actual_size)
 }
 
 /// # Safety
@@ -401,9 +599,12 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
     let next_ioctl = match IOCTL_FN_PTR {
         Some(func) => func,
         None => {
-            let next_ioctl = mem::transmute(libc::dlsym(RTLD_NEXT, b"ioctl\0".as_ptr() as _));
+            let next_ioctl = mem::transmute::<
+                *mut c_void,
+                unsafe extern "C" fn(RawFd, c_ulong, ...) -> c_int,
+            >(libc::dlsym(RTLD_NEXT, b"ioctl\0".as_ptr() as _));
 
-            IOCTL_FN_PTR = mem::transmute(next_ioctl);
+            IOCTL_FN_PTR = Some(next_ioctl);
 
             next_ioctl
         }
@@ -432,6 +633,8 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
 
     //info!("{:#x?}", io_data);
 
+    let config = CONFIG.get();
+
     macro_rules! check_size {
         ($name:ident, $expected_type:ty) => {
             check_size(
@@ -487,7 +690,7 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
             if check_size!(
                 NV2080_CTRL_CMD_BUS_GET_PCI_INFO,
                 Nv2080CtrlBusGetPciInfoParams
-            ) && CONFIG.unlock =>
+            ) && config.spoof_pci_info() =>
         {
             let params: &mut Nv2080CtrlBusGetPciInfoParams = &mut *io_data.params.cast();
 
@@ -497,7 +700,7 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
             let actual_device_id = (orig_device_id & 0xffff0000) >> 16;
             let actual_sub_system_id = (orig_sub_system_id & 0xffff0000) >> 16;
 
-            let mapped_id = CONFIG
+            let mapped_id = config
                 .pci_info_map
                 .as_ref()
                 .and_then(|pci_info_map| pci_info_map.get(&U32(actual_device_id)));
@@ -549,11 +752,11 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
         NV0080_CTRL_CMD_GPU_GET_VIRTUALIZATION_MODE
         // 18.0 driver sends larger struct with size 8 bytes. Only extra members added at the end,
         // nothing in between or changed, so accessing the larger struct is "safe"
-        if io_data.params_size == 8
+        if (io_data.params_size == 8
             || check_size!(
                 NV0080_CTRL_CMD_GPU_GET_VIRTUALIZATION_MODE,
                 Nv0080CtrlGpuGetVirtualizationModeParams
-            ) && CONFIG.unlock =>
+            )) && config.spoof_virtualization_mode() =>
         {
             let params: &mut Nv0080CtrlGpuGetVirtualizationModeParams = &mut *io_data.params.cast();
 
@@ -564,7 +767,7 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
             if check_size!(
                 NVA081_CTRL_CMD_VGPU_CONFIG_GET_MIGRATION_CAP,
                 NvA081CtrlCmdVgpuConfigGetMigrationCapParams
-            ) && CONFIG.unlock_migration =>
+            ) && config.unlock_migration =>
         {
             let params: &mut NvA081CtrlCmdVgpuConfigGetMigrationCapParams =
                 &mut *io_data.params.cast();
@@ -586,6 +789,10 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
                 info!("{:#?}", config);
 
                 *LAST_MDEV_UUID.lock() = Some(config.mdev_uuid);
+                *LAST_GPU.lock() = Some(PhysicalGpu {
+                    bdf: PciBdf(config.gpu_pci_bdf),
+                    pci_id: config.gpu_pci_id,
+                });
             }
             NV0000_CTRL_CMD_VGPU_CREATE_DEVICE
                 // 18.0 driver sends larger struct with size 40 bytes. Only extra members added at the end,
@@ -601,6 +808,10 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
                 info!("{:#?}", params);
 
                 *LAST_MDEV_UUID.lock() = Some(params.vgpu_name);
+                *LAST_GPU.lock() = Some(PhysicalGpu {
+                    bdf: PciBdf(params.gpu_pci_bdf),
+                    pci_id: params.gpu_pci_id,
+                });
             }
             NVA081_CTRL_CMD_VGPU_CONFIG_GET_VGPU_TYPE_INFO => {
                 if
@@ -616,7 +827,7 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
                         &mut *io_data.params.cast();
                     info!("{:#?}", params);
 
-                    if !handle_profile_override(&mut params.vgpu_type_info) {
+                    if !overrides::handle_profile_override(&mut params.vgpu_type_info) {
                         error!("Failed to apply profile override");
                         return -1;
                     }
@@ -630,7 +841,7 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
                         &mut *io_data.params.cast();
                     info!("{:#?}", params);
 
-                    if !handle_profile_override(&mut params.vgpu_type_info) {
+                    if !overrides::handle_profile_override(&mut params.vgpu_type_info) {
                         error!("Failed to apply profile override");
                         return -1;
                     }
@@ -651,7 +862,7 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
                         &mut *io_data.params.cast();
                     info!("{:#?}", params);
 
-                    if !handle_profile_override(params) {
+                    if !overrides::handle_profile_override(params) {
                         error!("Failed to apply profile override");
                         return -1;
                     }
@@ -660,7 +871,7 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
                         &mut *io_data.params.cast();
                     info!("{:#?}", params);
 
-                    if !handle_profile_override(params) {
+                    if !overrides::handle_profile_override(params) {
                         error!("Failed to apply profile override");
                         return -1;
                     }
@@ -683,7 +894,10 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
         // Things seems to work fine even if some operations that fail result in failed assertions.
         // So here we change the status value for these cases to cleanup the logs for
         // `nvidia-vgpu-mgr`.
-        if io_data.cmd == 0xa0820104 || io_data.cmd == NV9096_CTRL_CMD_GET_ZBC_CLEAR_TABLE {
+        if (io_data.cmd == 0xa0820104 && config.ignore_a0820104_failure())
+            || (io_data.cmd == NV9096_CTRL_CMD_GET_ZBC_CLEAR_TABLE
+                && config.ignore_zbc_clear_table_failure())
+        {
             io_data.status = NV_OK;
         } else {
             error!("cmd: {:#x} failed.", io_data.cmd);
@@ -693,299 +907,10 @@ pub unsafe extern "C" fn ioctl(fd: RawFd, request: c_ulong, argp: *mut c_void) -
     // Workaround for some Maxwell cards not supporting reading inforom.
     if io_data.cmd == NV2080_CTRL_CMD_GPU_GET_INFOROM_OBJECT_VERSION
         && io_data.status == NV_ERR_NOT_SUPPORTED
+        && config.inforom_workaround()
     {
         io_data.status = NV_ERR_OBJECT_NOT_FOUND;
     }
 
     ret
 }
-
-fn load_overrides() -> Result<String, bool> {
-    let config_path = match env::var_os("VGPU_UNLOCK_PROFILE_OVERRIDE_CONFIG_PATH") {
-        Some(path) => PathBuf::from(path),
-        None => PathBuf::from(DEFAULT_PROFILE_OVERRIDE_CONFIG_PATH),
-    };
-
-    let config_overrides = match fs::read_to_string(&config_path) {
-        Ok(data) => data,
-        Err(e) => {
-            if e.kind() == ErrorKind::NotFound {
-                error!("Config file '{}' not found", config_path.display());
-                return Err(true);
-            }
-
-            error!("Failed to read '{}': {}", config_path.display(), e);
-            return Err(false);
-        }
-    };
-
-    Ok(config_overrides)
-}
-
-fn handle_profile_override<C: VgpuConfigLike>(config: &mut C) -> bool {
-    let config_overrides = match load_overrides() {
-        Ok(overrides) => overrides,
-        Err(e) => return e,
-    };
-
-    let config_overrides: ProfileOverridesConfig = match toml::from_str(&config_overrides) {
-        Ok(config) => config,
-        Err(e) => {
-            error!("Failed to decode config: {}", e);
-            return false;
-        }
-    };
-
-    let vgpu_type = format!("nvidia-{}", config.vgpu_type());
-    let mdev_uuid = LAST_MDEV_UUID.lock().clone();
-
-    if let Some(config_override) = config_overrides.profile.get(vgpu_type.as_str()) {
-        info!("Applying profile {} overrides", vgpu_type);
-
-        if !apply_profile_override(config, &vgpu_type, config_override) {
-            return false;
-        }
-    }
-    if let Some(mdev_uuid) = mdev_uuid.map(|uuid| uuid.to_string()) {
-        if let Some(config_override) = config_overrides.mdev.get(mdev_uuid.as_str()) {
-            info!("Applying mdev UUID {} profile overrides", mdev_uuid);
-
-            if !apply_profile_override(config, &vgpu_type, config_override) {
-                return false;
-            }
-        }
-    }
-
-    #[cfg(feature = "proxmox")]
-    if let Some(vmid) = mdev_uuid.and_then(uuid_to_vmid) {
-        let vmid = vmid.to_string();
-        if let Some(config_override) = config_overrides.vm.get(vmid.as_str()) {
-            info!("Applying proxmox VMID {} profile overrides", vmid);
-
-            if !apply_profile_override(config, &vgpu_type, config_override) {
-                return false;
-            }
-        }
-    }
-
-    true
-}
-
-fn apply_profile_override<C: VgpuConfigLike>(
-    config: &mut C,
-    vgpu_type: &str,
-    config_override: &VgpuProfileOverride,
-) -> bool {
-    macro_rules! patch_msg {
-        ($target_field:ident, $value:expr) => {
-            info!(
-                "Patching {}/{}: {} -> {}",
-                vgpu_type,
-                stringify!($target_field),
-                config.$target_field(),
-                $value
-            );
-        };
-        ($target_field:ident, $preprocess:expr, $value:expr) => {
-            info!(
-                "Patching {}/{}: {} -> {}",
-                vgpu_type,
-                stringify!($target_field),
-                $preprocess(config.$target_field()),
-                $value
-            );
-        };
-    }
-    macro_rules! error_too_long {
-        ($target_field:ident, $value:expr) => {
-            error!(
-                "Patching {}/{}: value '{}' is too long",
-                vgpu_type,
-                stringify!($target_field),
-                $value
-            );
-
-            return false;
-        };
-    }
-
-    macro_rules! handle_override {
-        // Override entrypoint when the same field name is used as the source and target without
-        // an explicit `=>`.
-        (
-            class: $class:ident,
-            source_field: $field:ident,
-        ) => {
-            handle_override! {
-                class: $class,
-                source_field: $field,
-                target_field: $field,
-            }
-        };
-
-        // Override entrypoint when both the source and target field names are defined explicitly.
-        (
-            class: $class:ident,
-            source_field: $source_field:ident,
-            target_field: $target_field:ident,
-        ) => {
-            if let Some(value) = config_override.$source_field.as_ref() {
-                handle_override! {
-                    class: $class,
-                    value: value,
-                    source_field: $source_field,
-                    target_field: $target_field,
-                }
-            }
-        };
-
-        // The following are override handlers for each field class type (`bool`, `copy`, `str`,
-        // and `wide_str`).
-        (
-            class: bool,
-            value: $value:ident,
-            source_field: $source_field:ident,
-            target_field: $target_field:ident,
-        ) => {
-            let $value = cmp::max(cmp::min(*$value, 1), 0);
-
-            patch_msg!($target_field, $value);
-
-            *config.$target_field() = $value;
-        };
-        (
-            class: copy,
-            value: $value:ident,
-            source_field: $source_field:ident,
-            target_field: $target_field:ident,
-        ) => {
-            patch_msg!($target_field, $value);
-
-            *config.$target_field() = *$value;
-        };
-        (
-            class: str,
-            value: $value:ident,
-            source_field: $source_field:ident,
-            target_field: $target_field:ident,
-        ) => {
-            let value_bytes = $value.as_bytes();
-
-            // Use `len - 1` to account for the required NULL terminator.
-            if value_bytes.len() > config.$target_field().len() - 1 {
-                error_too_long!($target_field, $value);
-            } else {
-                patch_msg!($target_field, utils::from_c_str, $value);
-
-                // Zero out the field first.
-                // (`fill` was stabilized in Rust 1.50, but Debian Bullseye ships with 1.48)
-                for v in config.$target_field().iter_mut() {
-                    *v = 0;
-                }
-
-                // Write the string bytes.
-                let _ = config.$target_field()[..].as_mut().write_all(value_bytes);
-            }
-        };
-        (
-            class: wide_str,
-            value: $value:ident,
-            source_field: $source_field:ident,
-            target_field: $target_field:ident,
-        ) => {
-            // Use `len - 1` to account for the required NULL terminator.
-            if $value.encode_utf16().count() > config.$target_field().len() - 1 {
-                error_too_long!($target_field, $value);
-            } else {
-                patch_msg!($target_field, WideCharFormat, $value);
-
-                // Zero out the field first.
-                // (`fill` was stabilized in Rust 1.50, but Debian Bullseye ships with 1.48)
-                for v in config.$target_field().iter_mut() {
-                    *v = 0;
-                }
-
-                // Write the string bytes.
-                for (v, ch) in config.$target_field()[..]
-                    .iter_mut()
-                    .zip($value.encode_utf16().chain(Some(0)))
-                {
-                    *v = ch;
-                }
-            }
-        };
-    }
-    macro_rules! handle_overrides {
-        (
-            $($class:ident: [
-                $($source_field:ident $(=> $target_field:ident)?),*$(,)?
-            ]),*$(,)?
-        ) => {
-            $(
-                $(
-                    handle_override! {
-                        class: $class,
-                        source_field: $source_field,
-                        $(target_field: $target_field,)?
-                    }
-                )*
-            )*
-        };
-    }
-
-    // While the following could be done with fewer branches, I wanted the log statements to be in
-    // field order.
-
-    handle_overrides! {
-        copy: [
-            gpu_type => vgpu_type,
-        ],
-        str: [
-            card_name => vgpu_name,
-            vgpu_type => vgpu_class,
-            features => license,
-        ],
-        copy: [
-            max_instances => max_instance,
-            num_displays => num_heads,
-            display_width => max_resolution_x,
-            display_height => max_resolution_y,
-            max_pixels,
-            frl_config,
-        ],
-        bool: [
-            cuda_enabled,
-            ecc_supported,
-        ],
-        copy: [
-            mig_instance_size,
-        ],
-        bool: [
-            multi_vgpu_supported,
-        ],
-        copy: [
-            pci_id => vdev_id,
-            pci_device_id => pdev_id,
-            framebuffer => fb_length,
-            mappable_video_size,
-            framebuffer_reservation => fb_reservation,
-            encoder_capacity,
-            bar1_length,
-        ],
-        bool: [
-            frl_enabled => frl_enable,
-        ],
-        str: [
-            adapter_name,
-        ],
-        wide_str: [
-            adapter_name => adapter_name_unicode,
-        ],
-        str: [
-            short_gpu_name => short_gpu_name_string,
-            license_type => licensed_product_name,
-        ],
-    }
-
-    true
-}
@@ -1,9 +1,10 @@
-///! Sourced from:
-///! 525: https://github.com/NVIDIA/open-gpu-kernel-modules/blob/758b4ee8189c5198504cb1c3c5bc29027a9118a3/src/common/sdk/nvidia/inc/ctrl/ctrla081.h
-///! 580: https://github.com/NVIDIA/open-gpu-kernel-modules/blob/307159f2623d3bf45feb9177bd2da52ffbc5ddf9/src/common/sdk/nvidia/inc/ctrl/ctrla081.h
+//! Sourced from:
+//! 525: https://github.com/NVIDIA/open-gpu-kernel-modules/blob/758b4ee8189c5198504cb1c3c5bc29027a9118a3/src/common/sdk/nvidia/inc/ctrl/ctrla081.h
+//! 580: https://github.com/NVIDIA/open-gpu-kernel-modules/blob/307159f2623d3bf45feb9177bd2da52ffbc5ddf9/src/common/sdk/nvidia/inc/ctrl/ctrla081.h
 use std::fmt;
 
 use super::ctrl2080gpu::{NV2080_GPU_MAX_NAME_STRING_LENGTH, NV_GRID_LICENSE_INFO_MAX_LENGTH};
+use crate::extra_params::ExtraParamsFormat;
 use crate::format::{CStrFormat, HexFormat, HexFormatSlice, StraightFormat, WideCharFormat};
 use crate::utils::AlignedU64;
 
@@ -18,6 +19,7 @@ pub const NVA081_MAX_VGPU_PER_PGPU_V580: usize = 48;
 
 /// See `NVA081_CTRL_VGPU_CONFIG_INFO`
 // Set `align(8)` for `NVA081_CTRL_VGPU_CONFIG_GET_VGPU_TYPE_INFO_PARAMS`
+#[derive(Clone)]
 #[repr(C, align(8))]
 pub struct NvA081CtrlVgpuInfoV525 {
     pub vgpu_type: u32,
@@ -75,6 +77,7 @@ pub struct NvA081CtrlVgpuInfoV525 {
 
 /// See `NVA081_CTRL_VGPU_CONFIG_INFO`
 // Set `align(8)` for `NVA081_CTRL_VGPU_CONFIG_GET_VGPU_TYPE_INFO_PARAMS`
+#[derive(Clone)]
 #[repr(C, align(8))]
 pub struct NvA081CtrlVgpuInfoV580 {
     pub vgpu_type: u32,
@@ -157,11 +160,6 @@ impl fmt::Debug for NvA081CtrlVgpuInfoV525 {
         } else {
             &[]
         };
-        let vgpu_extra_params = if self.vgpu_extra_params[..].iter().any(|&x| x != 0) {
-            &self.vgpu_extra_params[..]
-        } else {
-            &[]
-        };
 
         f.debug_struct("NvA081CtrlVgpuInfoV525")
             .field("vgpu_type", &self.vgpu_type)
@@ -202,7 +200,10 @@ impl fmt::Debug for NvA081CtrlVgpuInfoV525 {
                 "licensed_product_name",
                 &CStrFormat(&self.licensed_product_name),
             )
-            .field("vgpu_extra_params", &HexFormatSlice(vgpu_extra_params))
+            .field(
+                "vgpu_extra_params",
+                &ExtraParamsFormat(&self.vgpu_extra_params[..]),
+            )
             .field("ftrace_enable", &self.ftrace_enable)
             .field("gpu_direct_supported", &self.gpu_direct_supported)
             .field("nvlink_p2p_supported", &self.nvlink_p2p_supported)
@@ -221,11 +222,6 @@ impl fmt::Debug for NvA081CtrlVgpuInfoV580 {
         } else {
             &[]
         };
-        let vgpu_extra_params = if self.vgpu_extra_params[..].iter().any(|&x| x != 0) {
-            &self.vgpu_extra_params[..]
-        } else {
-            &[]
-        };
 
         f.debug_struct("NvA081CtrlVgpuInfoV580")
             .field("vgpu_type", &self.vgpu_type)
@@ -266,7 +262,10 @@ impl fmt::Debug for NvA081CtrlVgpuInfoV580 {
                 "licensed_product_name",
                 &CStrFormat(&self.licensed_product_name),
             )
-            .field("vgpu_extra_params", &HexFormatSlice(vgpu_extra_params))
+            .field(
+                "vgpu_extra_params",
+                &ExtraParamsFormat(&self.vgpu_extra_params[..]),
+            )
             .field("ftrace_enable", &self.ftrace_enable)
             .field("gpu_direct_supported", &self.gpu_direct_supported)
             .field("nvlink_p2p_supported", &self.nvlink_p2p_supported)
@@ -275,6 +274,7 @@ impl fmt::Debug for NvA081CtrlVgpuInfoV580 {
             .field("exclusive_type", &self.exclusive_type)
             .field("exclusive_size", &self.exclusive_size)
             .field("gpu_instance_profile_id", &self.gpu_instance_profile_id)
+            .field("placement_size", &self.placement_size)
             .field(
                 "homogeneous_placement_count",
                 &self.homogeneous_placement_count,
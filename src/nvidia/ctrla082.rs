@@ -5,12 +5,14 @@ use super::ctrla081::{
     NVA081_EXTRA_PARAMETERS_SIZE, NVA081_MAX_VGPU_PER_PGPU_V580, NVA081_VGPU_SIGNATURE_SIZE,
     NVA081_VGPU_STRING_BUFFER_SIZE_V525, NVA081_VGPU_STRING_BUFFER_SIZE_V580,
 };
+use crate::extra_params::ExtraParamsFormat;
 use crate::format::{CStrFormat, HexFormat, HexFormatSlice, StraightFormat, WideCharFormat};
 
 /// Inferred based on `NVA082_CTRL_CMD_HOST_VGPU_DEVICE_GET_VGPU_TYPE_INFO_PARAMS`
 pub const NVA082_CTRL_CMD_HOST_VGPU_DEVICE_GET_VGPU_TYPE_INFO: u32 = 0xa0820102;
 
 /// Pulled from a comment in [`NVA081_CTRL_VGPU_INFO`](https://github.com/NVIDIA/open-gpu-kernel-modules/blob/758b4ee8189c5198504cb1c3c5bc29027a9118a3/src/common/sdk/nvidia/inc/ctrl/ctrla081.h#L82)
+#[derive(Clone)]
 #[repr(C)]
 pub struct NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 {
     pub vgpu_type: u32,
@@ -47,6 +49,7 @@ pub struct NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 {
 }
 
 /// Pulled from a comment in [`NVA081_CTRL_VGPU_INFO`](https://github.com/NVIDIA/open-gpu-kernel-modules/blob/307159f2623d3bf45feb9177bd2da52ffbc5ddf9/src/common/sdk/nvidia/inc/ctrl/ctrla081.h#L89)
+#[derive(Clone)]
 #[repr(C)]
 pub struct NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV580 {
     pub vgpu_type: u32,
@@ -99,11 +102,6 @@ impl fmt::Debug for NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 {
         } else {
             &[]
         };
-        let vgpu_extra_params = if self.vgpu_extra_params[..].iter().any(|&x| x != 0) {
-            &self.vgpu_extra_params[..]
-        } else {
-            &[]
-        };
 
         f.debug_struct("NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525")
             .field("vgpu_type", &self.vgpu_type)
@@ -142,7 +140,10 @@ impl fmt::Debug for NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 {
                 "licensed_product_name",
                 &CStrFormat(&self.licensed_product_name),
             )
-            .field("vgpu_extra_params", &HexFormatSlice(vgpu_extra_params))
+            .field(
+                "vgpu_extra_params",
+                &ExtraParamsFormat(&self.vgpu_extra_params[..]),
+            )
             .finish()
     }
 }
@@ -154,11 +155,6 @@ impl fmt::Debug for NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV580 {
         } else {
             &[]
         };
-        let vgpu_extra_params = if self.vgpu_extra_params[..].iter().any(|&x| x != 0) {
-            &self.vgpu_extra_params[..]
-        } else {
-            &[]
-        };
 
         f.debug_struct("NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV580")
             .field("vgpu_type", &self.vgpu_type)
@@ -197,7 +193,10 @@ impl fmt::Debug for NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV580 {
                 "licensed_product_name",
                 &CStrFormat(&self.licensed_product_name),
             )
-            .field("vgpu_extra_params", &HexFormatSlice(vgpu_extra_params))
+            .field(
+                "vgpu_extra_params",
+                &ExtraParamsFormat(&self.vgpu_extra_params[..]),
+            )
             .field("ftrace_enable", &self.ftrace_enable)
             .field("gpu_direct_supported", &self.gpu_direct_supported)
             .field("nvlink_p2p_supported", &self.nvlink_p2p_supported)
@@ -206,6 +205,7 @@ impl fmt::Debug for NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV580 {
             .field("exclusive_type", &self.exclusive_type)
             .field("exclusive_size", &self.exclusive_size)
             .field("gpu_instance_profile_id", &self.gpu_instance_profile_id)
+            .field("placement_size", &self.placement_size)
             .field(
                 "homogeneous_placement_count",
                 &self.homogeneous_placement_count,
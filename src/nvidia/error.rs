@@ -1,11 +1,11 @@
 #![allow(unused)]
 
-///! When ioctl returns success (retval >= 0) but sets the status value of the arg structure to 3
-///! then `nvidia-vgpud` will sleep for a bit (first 0.1s then 1s then 10s) then issue the same
-///! ioctl call again until the status differs from 3. It will attempt this for up to 24h before
-///! giving up.
-///!
-///! Sourced from https://github.com/NVIDIA/open-gpu-kernel-modules/blob/5f40a5aee5ef9c92085836bf5b5a9056174f07f1/kernel-open/common/inc/nvstatuscodes.h
+//! When ioctl returns success (retval >= 0) but sets the status value of the arg structure to 3
+//! then `nvidia-vgpud` will sleep for a bit (first 0.1s then 1s then 10s) then issue the same
+//! ioctl call again until the status differs from 3. It will attempt this for up to 24h before
+//! giving up.
+//!
+//! Sourced from https://github.com/NVIDIA/open-gpu-kernel-modules/blob/5f40a5aee5ef9c92085836bf5b5a9056174f07f1/kernel-open/common/inc/nvstatuscodes.h
 
 pub const NV_OK: u32 = 0x00000000;
 pub const NV_ERR_GENERIC: u32 = 0x0000ffff;
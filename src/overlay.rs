@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: MIT
+
+//! Overlay of `VGPU_UNLOCK_*` environment variables on top of the configuration files.
+//!
+//! The name of the variable is the dotted path of the key in upper case, with the dots replaced
+//! by underscores and prefixed by `VGPU_UNLOCK_`. For example `unlock_migration` is set by
+//! `VGPU_UNLOCK_UNLOCK_MIGRATION` and `pci_info_map.0x1e84.device_id` is set by
+//! `VGPU_UNLOCK_PCI_INFO_MAP_0X1E84_DEVICE_ID`. Keys of maps are lower cased, and keys of
+//! `pci_info_map` are lower cased in the files as well so they are merged with the variables.
+//!
+//! The value is parsed as a TOML value and used as a string if that fails.
+
+use std::env;
+
+use toml::{Table, Value};
+
+use crate::layered::{self, Layered, Source};
+use crate::schema::Schema;
+use crate::validate;
+
+const PREFIX: &str = "VGPU_UNLOCK_";
+
+/// Merges every `VGPU_UNLOCK_*` environment variable that names a key allowed by `schema` on top
+/// of `layered`. Variables that do not name a key are ignored.
+pub fn apply(layered: &mut Layered, schema: &Schema) {
+    // `env::vars` panics on variables that are not valid unicode, skip those instead.
+    let mut vars: Vec<(String, String)> = env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(name, _)| name.starts_with(PREFIX))
+        .collect();
+
+    // Apply in a stable order in case two variables name the same key.
+    vars.sort();
+
+    for (name, value) in vars {
+        if let Some(table) = overlay(&name, &value, schema) {
+            layered::merge(layered, table, &Source::Env(name));
+        }
+    }
+}
+
+/// Returns a table holding only the key the environment variable `name` sets to `value`, or
+/// `None` if it does not name a key allowed by `schema`.
+fn overlay(name: &str, value: &str, schema: &Schema) -> Option<Table> {
+    let path = resolve(schema, name.strip_prefix(PREFIX)?)?;
+    let value = parse_value(value);
+
+    let table = path.into_iter().rev().fold(value, |value, key| {
+        let mut table = Table::new();
+        table.insert(key, value);
+
+        Value::Table(table)
+    });
+
+    match table {
+        Value::Table(table) => Some(table),
+        _ => None,
+    }
+}
+
+/// Returns the path of the key `name` refers to, trying every possible split of the name for
+/// keys of maps.
+fn resolve(schema: &Schema, name: &str) -> Option<Vec<String>> {
+    match schema {
+        // Only fields of the struct are considered, which leaves out the per-process sections as
+        // environment variables are already specific to a process.
+        Schema::Struct(fields, nested) => fields.iter().find_map(|&field| {
+            let nested = nested
+                .iter()
+                .find(|(nested, _)| *nested == field)
+                .map(|(_, schema)| schema);
+            let upper = field.to_ascii_uppercase();
+
+            match nested {
+                // Only leaf keys can be set.
+                None if name == upper => Some(vec![field.to_string()]),
+                None => None,
+                Some(schema) => {
+                    let rest = name.strip_prefix(&upper)?.strip_prefix('_')?;
+                    let mut path = resolve(schema, rest)?;
+
+                    path.insert(0, field.to_string());
+
+                    Some(path)
+                }
+            }
+        }),
+        // Arrays of tables can not be set by environment variables.
+        Schema::Array(_) => None,
+        // Environment variables are always read with the current names.
+        Schema::Renamed(_, schema) => resolve(schema, name),
+        Schema::Restricted(keys, _, schema) => {
+            resolve(schema, name).filter(|path| !keys.contains(&path[0].as_str()))
+        }
+        Schema::Map(schema) => name.match_indices('_').find_map(|(i, _)| {
+            let mut path = resolve(schema, &name[i + 1..])?;
+
+            path.insert(0, name[..i].to_ascii_lowercase());
+
+            Some(path)
+        }),
+        Schema::Keyed(keys, schema) => name.match_indices('_').find_map(|(i, _)| {
+            let key = name[..i].to_ascii_lowercase();
+
+            if !validate::is_valid_key(*keys, &key) {
+                return None;
+            }
+
+            let mut path = resolve(schema, &name[i + 1..])?;
+
+            path.insert(0, validate::normal_key(*keys, &key));
+
+            Some(path)
+        }),
+    }
+}
+
+fn parse_value(value: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use toml::Value;
+
+    use super::{overlay, parse_value};
+    use crate::config::Config;
+    use crate::layered::{self, Source};
+    use crate::permissions::StrictModes;
+    use crate::schema::CONFIG;
+    use crate::validate::Strictness;
+
+    #[test]
+    fn test_overlay() {
+        assert_eq!(
+            overlay("VGPU_UNLOCK_UNLOCK", "false", &CONFIG)
+                .unwrap()
+                .to_string(),
+            "unlock = false\n"
+        );
+        assert_eq!(
+            overlay("VGPU_UNLOCK_UNLOCK_MIGRATION", "true", &CONFIG)
+                .unwrap()
+                .to_string(),
+            "unlock_migration = true\n"
+        );
+        assert_eq!(
+            overlay(
+                "VGPU_UNLOCK_PCI_INFO_MAP_0X1E84_SUB_SYSTEM_ID",
+                "0x12ba",
+                &CONFIG
+            )
+            .unwrap()
+            .to_string(),
+            "[pci_info_map.0x1e84]\nsub_system_id = 4794\n"
+        );
+
+        assert!(overlay("VGPU_UNLOCK_PCI_INFO_MAP", "1", &CONFIG).is_none());
+        assert!(overlay("VGPU_UNLOCK_CONFIG_PATH", "/tmp", &CONFIG).is_none());
+        assert!(overlay("UNLOCK", "true", &CONFIG).is_none());
+    }
+
+    #[test]
+    fn test_pci_info_map_case() {
+        let dir = env::temp_dir().join(format!("vgpu_unlock-test-overlay-{}", process::id()));
+        let path = dir.join("config.toml");
+
+        fs::create_dir_all(dir.join("config.d")).unwrap();
+        fs::write(
+            &path,
+            "[pci_info_map.0x1E84]\ndevice_id = 0x1e30\nsub_system_id = 0x12ba\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("config.d/10-case.toml"),
+            "[pci_info_map.0X1e84]\nsub_system_id = 0x12bb\n",
+        )
+        .unwrap();
+
+        let mut layered = layered::load(&path, &CONFIG, Strictness::Strict, StrictModes::Yes)
+            .ok()
+            .unwrap()
+            .unwrap();
+        let name = "VGPU_UNLOCK_PCI_INFO_MAP_0X1E84_DEVICE_ID";
+        let table = overlay(name, "0x1e31", &CONFIG).unwrap();
+
+        layered::merge(&mut layered, table, &Source::Env(name.to_string()));
+
+        let config: Config = Value::Table(layered.table).try_into().unwrap();
+        let pci_info_map = config.pci_info_map.unwrap();
+        assert_eq!(pci_info_map.len(), 1);
+
+        let entry = pci_info_map.values().next().unwrap();
+        assert_eq!(entry.device_id, 0x1e31);
+        assert_eq!(entry.sub_system_id, 0x12bb);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_value("true"), Value::Boolean(true));
+        assert_eq!(parse_value("0x10"), Value::Integer(16));
+        assert_eq!(parse_value("'quoted'"), Value::String("quoted".into()));
+        assert_eq!(
+            parse_value("bare words"),
+            Value::String("bare words".into())
+        );
+    }
+}
@@ -0,0 +1,2686 @@
+// SPDX-License-Identifier: MIT
+
+//! Overrides of the vGPU profiles reported by the driver, read from `profile_override.toml`.
+//!
+//! Overrides are a list of rules applied in order to every vGPU profile the driver reports. Each
+//! rule matches on any combination of the vGPU type, its name and class, the mdev UUID, the
+//! Proxmox VMID, and the physical GPU the vGPU is created on, and can stop the rules after it from
+//! applying. The `[profile]`, `[gpu]`, `[group]`, `[mdev]`, and `[vm]` tables are shorthands for
+//! rules matching a single criterion, and are applied before the `[[rule]]` entries in that order.
+//!
+//! Keys of `[profile]` are vGPU types such as `nvidia-55`, and keys of `[profile_name]` are globs
+//! matched against the vGPU name such as `"GRID RTX6000-*Q"`. The globs apply first in lexical
+//! order, followed by the vGPU types, so overrides for a specific type win over overrides for a
+//! pattern. `nvidia-vgpud` lists the profiles of every vGPU type, and a name without wildcards
+//! that none of them has is logged as an error. The vGPU class is only matched by rules.
+//!
+//! Keys of `[vm]` are either a single Proxmox VMID or an inclusive range such as `1000-1999`.
+//! Ranges apply before single VMIDs, and wider ranges before the narrower ones they overlap, so
+//! the most specific match wins. A `[group.<name>]` lists VMIDs and mdev UUIDs sharing an
+//! override, and applies before both `[mdev]` and `[vm]`.
+
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryInto;
+use std::env;
+#[cfg(feature = "proxmox")]
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "proxmox")]
+use std::str::FromStr;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+#[cfg(feature = "proxmox")]
+use serde::de::{self, Deserializer, Unexpected, Visitor};
+use serde::Deserialize;
+use toml::Value;
+
+use crate::consistency;
+use crate::explain::{self, Explanation};
+use crate::extra_params::{self, SetError};
+use crate::format::WideCharFormat;
+use crate::human_number::{self, EvalError, Expr};
+use crate::layered::{self, FileStamp, Source};
+use crate::log::{error, info};
+use crate::nvidia::ctrla081::NVA081_MAX_VGPU_PER_PGPU_V580;
+use crate::pci::{PciBdf, PhysicalGpu};
+use crate::permissions::StrictModes;
+use crate::resolution::Resolution;
+use crate::schema;
+use crate::utils;
+#[cfg(feature = "proxmox")]
+use crate::utils::uuid_to_vmid;
+use crate::uuid::Uuid;
+use crate::validate::Strictness;
+use crate::{VgpuConfigLike, LAST_GPU, LAST_MDEV_UUID};
+
+const DEFAULT_PROFILE_OVERRIDE_CONFIG_PATH: &str = "/etc/vgpu_unlock/profile_override.toml";
+
+#[derive(Deserialize)]
+struct ProfileOverridesConfig {
+    /// Log the patches of every rule without applying them.
+    #[serde(default)]
+    dry_run: bool,
+    /// Overrides by vGPU type, keyed like `nvidia-55`.
+    #[serde(default)]
+    profile: BTreeMap<String, VgpuProfileOverride>,
+    /// Overrides by vGPU name, keyed by a glob.
+    #[serde(default)]
+    profile_name: BTreeMap<String, VgpuProfileOverride>,
+    /// Overrides for every vGPU on the physical GPU at a PCI address. Only `nvidia-vgpu-mgr` knows
+    /// the physical GPU, so they never apply in `nvidia-vgpud`.
+    #[serde(default)]
+    gpu: BTreeMap<String, VgpuProfileOverride>,
+    #[serde(default)]
+    group: BTreeMap<String, Group>,
+    #[serde(default)]
+    mdev: BTreeMap<String, VgpuProfileOverride>,
+    #[cfg(feature = "proxmox")]
+    #[serde(default)]
+    vm: BTreeMap<String, VgpuProfileOverride>,
+    #[serde(default)]
+    rule: Vec<Rule>,
+}
+
+/// A `[group.<name>]` entry. The overrides apply to every vGPU of the listed VMs and mdevs.
+#[derive(Deserialize)]
+struct Group {
+    #[cfg(feature = "proxmox")]
+    #[serde(default)]
+    vmids: Vec<VmidRange>,
+    #[serde(default)]
+    mdevs: Vec<String>,
+    #[serde(default, rename = "override")]
+    config_override: VgpuProfileOverride,
+}
+
+/// The members of a group, matching a vGPU when any of them does.
+#[derive(Default)]
+struct Members {
+    #[cfg(feature = "proxmox")]
+    vmids: Vec<VmidRange>,
+    mdevs: Vec<String>,
+}
+
+impl Members {
+    fn matches(&self, target: &Target) -> bool {
+        self.mdevs.iter().any(|mdev| matches_mdev(mdev, target)) || self.matches_vmid(target)
+    }
+
+    #[cfg(feature = "proxmox")]
+    fn matches_vmid(&self, target: &Target) -> bool {
+        match target.mdev.and_then(uuid_to_vmid) {
+            Some(vmid) => self.vmids.iter().any(|range| range.contains(vmid)),
+            None => false,
+        }
+    }
+
+    #[cfg(not(feature = "proxmox"))]
+    fn matches_vmid(&self, _target: &Target) -> bool {
+        false
+    }
+}
+
+/// An inclusive range of Proxmox VMIDs, written as `100` or `"1000-1999"`.
+#[cfg(feature = "proxmox")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VmidRange {
+    start: u64,
+    end: u64,
+}
+
+#[cfg(feature = "proxmox")]
+impl VmidRange {
+    #[inline]
+    fn contains(self, vmid: u64) -> bool {
+        (self.start..=self.end).contains(&vmid)
+    }
+
+    #[inline]
+    fn is_single(self) -> bool {
+        self.start == self.end
+    }
+}
+
+#[cfg(feature = "proxmox")]
+impl fmt::Display for VmidRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_single() {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+#[cfg(feature = "proxmox")]
+impl FromStr for VmidRange {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => (s.trim(), s.trim()),
+        };
+        let start = start.parse().map_err(|_| ())?;
+        let end = end.parse().map_err(|_| ())?;
+
+        if start > end {
+            return Err(());
+        }
+
+        Ok(VmidRange { start, end })
+    }
+}
+
+#[cfg(feature = "proxmox")]
+impl<'de> Deserialize<'de> for VmidRange {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(VmidRangeVisitor)
+    }
+}
+
+#[cfg(feature = "proxmox")]
+struct VmidRangeVisitor;
+
+#[cfg(feature = "proxmox")]
+impl<'de> Visitor<'de> for VmidRangeVisitor {
+    type Value = VmidRange;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("VMID such as 100 or range of VMIDs such as \"1000-1999\"")
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        if v < 0 {
+            return Err(E::invalid_value(Unexpected::Signed(v), &self));
+        }
+
+        self.visit_u64(v as u64)
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(VmidRange { start: v, end: v })
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        v.parse()
+            .map_err(|_| E::invalid_value(Unexpected::Str(v), &self))
+    }
+}
+
+/// A `[[rule]]` entry. The overrides apply to every vGPU matching all criteria of `match`.
+#[derive(Deserialize)]
+struct Rule {
+    #[serde(default, rename = "match")]
+    criteria: Criteria,
+    #[serde(default, rename = "override")]
+    config_override: VgpuProfileOverride,
+    /// Skip the rules after this one when it matches.
+    #[serde(default)]
+    stop: bool,
+    /// Log the patches of this rule without applying them.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// The criteria of a rule. Unset criteria match every vGPU.
+#[derive(Default, Deserialize)]
+struct Criteria {
+    vgpu_type_id: Option<u32>,
+    /// Glob matched against the vGPU name, e.g. `GRID RTX6000-4Q`.
+    vgpu_name: Option<String>,
+    /// Glob matched against the vGPU class, e.g. `Quadro`.
+    vgpu_class: Option<String>,
+    mdev: Option<String>,
+    #[cfg(feature = "proxmox")]
+    vmid: Option<VmidRange>,
+    gpu_bdf: Option<PciBdf>,
+    device_id: Option<u16>,
+    /// Set for the rules made from `[group]`, which are not written as a criterion.
+    #[serde(skip)]
+    members: Option<Members>,
+}
+
+impl Criteria {
+    fn matches(&self, target: &Target) -> bool {
+        self.vgpu_type_id
+            .map_or(true, |id| id == target.vgpu_type_id)
+            && self
+                .vgpu_name
+                .as_ref()
+                .map_or(true, |name| utils::glob_match(name, &target.vgpu_name))
+            && self
+                .vgpu_class
+                .as_ref()
+                .map_or(true, |class| utils::glob_match(class, &target.vgpu_class))
+            && self
+                .mdev
+                .as_ref()
+                .map_or(true, |mdev| matches_mdev(mdev, target))
+            && self.matches_vmid(target)
+            && self
+                .gpu_bdf
+                .map_or(true, |bdf| target.gpu.is_some_and(|gpu| gpu.bdf == bdf))
+            && self.device_id.map_or(true, |device_id| {
+                target.gpu.is_some_and(|gpu| gpu.device_id() == device_id)
+            })
+            && self
+                .members
+                .as_ref()
+                .map_or(true, |members| members.matches(target))
+    }
+
+    #[cfg(feature = "proxmox")]
+    fn matches_vmid(&self, target: &Target) -> bool {
+        match self.vmid {
+            Some(range) => target
+                .mdev
+                .and_then(uuid_to_vmid)
+                .is_some_and(|vmid| range.contains(vmid)),
+            None => true,
+        }
+    }
+
+    #[cfg(not(feature = "proxmox"))]
+    fn matches_vmid(&self, _target: &Target) -> bool {
+        true
+    }
+}
+
+fn matches_mdev(mdev: &str, target: &Target) -> bool {
+    target
+        .mdev
+        .is_some_and(|uuid| uuid.to_string().eq_ignore_ascii_case(mdev))
+}
+
+/// Parses a vGPU type such as `nvidia-55`.
+pub fn vgpu_type_id(key: &str) -> Option<u32> {
+    key.strip_prefix("nvidia-")?.parse().ok()
+}
+
+/// The vGPU profile the rules are matched against.
+struct Target {
+    vgpu_type_id: u32,
+    vgpu_name: String,
+    vgpu_class: String,
+    mdev: Option<Uuid>,
+    gpu: Option<PhysicalGpu>,
+}
+
+/// The fields a rule overrides. The numeric fields other than the flags take an [`Expr`], which is
+/// evaluated against the values the driver originally reported for the profile.
+#[derive(Default, Deserialize)]
+struct VgpuProfileOverride {
+    #[serde(default, with = "human_number")]
+    gpu_type: Option<Expr>,
+    card_name: Option<String>,
+    vgpu_type: Option<String>,
+    features: Option<String>,
+    #[serde(default, with = "human_number")]
+    max_instances: Option<Expr>,
+    #[serde(default, with = "human_number")]
+    num_displays: Option<Expr>,
+    #[serde(default, with = "human_number")]
+    display_width: Option<Expr>,
+    #[serde(default, with = "human_number")]
+    display_height: Option<Expr>,
+    /// Sets `display_width` and `display_height`, and `max_pixels` to fit every display at this
+    /// resolution unless it is set as well.
+    resolution: Option<Resolution>,
+    #[serde(default, with = "human_number")]
+    max_pixels: Option<Expr>,
+    #[serde(default, with = "human_number")]
+    frl_config: Option<Expr>,
+    cuda_enabled: Option<u32>,
+    ecc_supported: Option<u32>,
+    #[serde(default, with = "human_number")]
+    mig_instance_size: Option<Expr>,
+    multi_vgpu_supported: Option<u32>,
+    #[serde(default, with = "human_number")]
+    pci_id: Option<Expr>,
+    #[serde(default, with = "human_number")]
+    pci_device_id: Option<Expr>,
+    /// Only present in the profiles of `NVA081_CTRL_CMD_VGPU_CONFIG_GET_VGPU_TYPE_INFO`.
+    #[serde(default, with = "human_number")]
+    profile_size: Option<Expr>,
+    #[serde(default, with = "human_number")]
+    framebuffer: Option<Expr>,
+    /// Only present in the profiles of `NVA081_CTRL_CMD_VGPU_CONFIG_GET_VGPU_TYPE_INFO`.
+    #[serde(default, with = "human_number")]
+    gsp_heap_size: Option<Expr>,
+    #[serde(default, with = "human_number")]
+    mappable_video_size: Option<Expr>,
+    #[serde(default, with = "human_number")]
+    framebuffer_reservation: Option<Expr>,
+    #[serde(default, with = "human_number")]
+    encoder_capacity: Option<Expr>,
+    #[serde(default, with = "human_number")]
+    bar1_length: Option<Expr>,
+    frl_enabled: Option<u32>,
+    adapter_name: Option<String>,
+    /// The name shown by Windows guests, which may hold any character unlike `adapter_name`.
+    /// Follows `adapter_name` when not set.
+    adapter_name_unicode: Option<String>,
+    short_gpu_name: Option<String>,
+    license_type: Option<String>,
+    /// Parameters of `vgpu_extra_params` by index.
+    extra_params: Option<BTreeMap<String, u32>>,
+    /// The following are not present in the profiles of `nvidia-vgpu-mgr` before R580.
+    ftrace_enable: Option<u32>,
+    gpu_direct_supported: Option<u32>,
+    nvlink_p2p_supported: Option<u32>,
+    multi_vgpu_exclusive: Option<u32>,
+    #[serde(default, with = "human_number")]
+    exclusive_type: Option<Expr>,
+    #[serde(default, with = "human_number")]
+    exclusive_size: Option<Expr>,
+    #[serde(default, with = "human_number")]
+    gpu_instance_profile_id: Option<Expr>,
+    /// The following are only present in the profiles of R580 and later drivers.
+    #[serde(default, with = "human_number")]
+    max_instance_per_gi: Option<Expr>,
+    #[serde(default, with = "human_number")]
+    placement_size: Option<Expr>,
+    homogeneous_placement_ids: Option<Vec<u32>>,
+    heterogeneous_placement_ids: Option<Vec<u32>>,
+    /// Replaces both placement tables with one placement per instance, sized after the
+    /// framebuffer.
+    #[serde(default)]
+    regenerate_placements: bool,
+}
+
+impl VgpuProfileOverride {
+    /// Removes the overrides of the fields `nvidia-vgpud` reports for the mdev types, which it can
+    /// not apply per physical GPU as it does not know the GPU a profile is queried for. Returns
+    /// the keys of the removed overrides.
+    fn remove_vgpud_reported(&mut self) -> Vec<&'static str> {
+        let mut removed = Vec::new();
+
+        macro_rules! remove {
+            ($($field:ident),* $(,)?) => {
+                $(
+                    if self.$field.take().is_some() {
+                        removed.push(stringify!($field));
+                    }
+                )*
+            };
+        }
+
+        // Keep in sync with `schema::VGPUD_REPORTED_FIELDS`.
+        remove!(
+            card_name,
+            max_instances,
+            num_displays,
+            display_width,
+            display_height,
+            resolution,
+            frl_config,
+            framebuffer,
+        );
+
+        removed
+    }
+
+    /// Returns the keys set along with `resolution` that it overrides in turn.
+    fn resolution_conflicts(&self) -> Vec<&'static str> {
+        if self.resolution.is_none() {
+            return Vec::new();
+        }
+
+        vec![
+            ("display_width", self.display_width.is_some()),
+            ("display_height", self.display_height.is_some()),
+        ]
+        .into_iter()
+        .filter(|&(_, set)| set)
+        .map(|(key, _)| key)
+        .collect()
+    }
+}
+
+/// The rules of `profile_override.toml` in the order they apply.
+pub struct Overrides {
+    rules: Vec<Entry>,
+    /// Every rule is a dry run.
+    dry_run: bool,
+    /// Where each key of the configuration was set.
+    sources: BTreeMap<String, Source>,
+    /// Key paths of the rules already logged for matching no vGPU name.
+    unmatched_reported: Mutex<BTreeSet<String>>,
+}
+
+/// A rule along with where it was written.
+struct Entry {
+    /// Description of the rule for the logs.
+    label: String,
+    /// Key path of the overrides of the rule, e.g. `profile.nvidia-55`.
+    path: String,
+    rule: Rule,
+}
+
+impl From<ProfileOverridesConfig> for Overrides {
+    fn from(config: ProfileOverridesConfig) -> Self {
+        let mut rules = Vec::new();
+        let mut add = |label: String,
+                       path: String,
+                       criteria: Criteria,
+                       config_override: VgpuProfileOverride| {
+            let rule = Rule {
+                criteria,
+                config_override,
+                stop: false,
+                dry_run: false,
+            };
+
+            rules.push(Entry { label, path, rule });
+        };
+
+        // Globs are visited in lexical order as `profile_name` is sorted.
+        for (key, config_override) in config.profile_name {
+            let criteria = Criteria {
+                vgpu_name: Some(key.clone()),
+                ..Default::default()
+            };
+
+            add(
+                format!("profile name {:?}", key),
+                layered::key_path("profile_name", &key),
+                criteria,
+                config_override,
+            );
+        }
+
+        let mut types = Vec::new();
+
+        for (key, config_override) in config.profile {
+            // Invalid keys are reported by the validation of the files.
+            if let Some(vgpu_type_id) = vgpu_type_id(&key) {
+                types.push((vgpu_type_id, key, config_override));
+            }
+        }
+
+        types.sort_by_key(|&(vgpu_type_id, _, _)| vgpu_type_id);
+
+        for (vgpu_type_id, key, config_override) in types {
+            let criteria = Criteria {
+                vgpu_type_id: Some(vgpu_type_id),
+                ..Default::default()
+            };
+
+            add(
+                format!("profile {}", key),
+                layered::key_path("profile", &key),
+                criteria,
+                config_override,
+            );
+        }
+
+        let mut gpus = Vec::new();
+
+        for (key, config_override) in config.gpu {
+            // Invalid keys are reported by the validation of the files.
+            if let Ok(bdf) = key.parse::<PciBdf>() {
+                gpus.push((bdf, key, config_override));
+            }
+        }
+
+        // Both `0000:41:00.0` and `41:00.0` are accepted, so sort by the parsed address.
+        gpus.sort_by_key(|&(bdf, _, _)| bdf);
+
+        for (bdf, key, mut config_override) in gpus {
+            // Reported by the validation of the files.
+            config_override.remove_vgpud_reported();
+
+            let criteria = Criteria {
+                gpu_bdf: Some(bdf),
+                ..Default::default()
+            };
+
+            add(
+                format!("GPU {}", bdf),
+                layered::key_path("gpu", &key),
+                criteria,
+                config_override,
+            );
+        }
+        for (name, group) in config.group {
+            let members = Members {
+                #[cfg(feature = "proxmox")]
+                vmids: group.vmids,
+                mdevs: group.mdevs,
+            };
+            let criteria = Criteria {
+                members: Some(members),
+                ..Default::default()
+            };
+
+            add(
+                format!("group {}", name),
+                layered::key_path(&layered::key_path("group", &name), "override"),
+                criteria,
+                group.config_override,
+            );
+        }
+        for (key, config_override) in config.mdev {
+            let criteria = Criteria {
+                mdev: Some(key.clone()),
+                ..Default::default()
+            };
+
+            add(
+                format!("mdev UUID {}", key),
+                layered::key_path("mdev", &key),
+                criteria,
+                config_override,
+            );
+        }
+        #[cfg(feature = "proxmox")]
+        {
+            let mut vms = Vec::new();
+
+            for (key, config_override) in config.vm {
+                // Invalid keys are reported by the validation of the files.
+                if let Ok(range) = key.parse::<VmidRange>() {
+                    vms.push((range, key, config_override));
+                }
+            }
+
+            // Ranges before single VMIDs, wider ranges before narrower ones.
+            vms.sort_by_key(|&(range, _, _)| (cmp::Reverse(range.end - range.start), range.start));
+
+            for (range, key, config_override) in vms {
+                let label = if range.is_single() {
+                    format!("proxmox VMID {}", range)
+                } else {
+                    format!("proxmox VMIDs {}", range)
+                };
+                let criteria = Criteria {
+                    vmid: Some(range),
+                    ..Default::default()
+                };
+
+                add(
+                    label,
+                    layered::key_path("vm", &key),
+                    criteria,
+                    config_override,
+                );
+            }
+        }
+
+        for (i, mut rule) in config.rule.into_iter().enumerate() {
+            if rule.criteria.gpu_bdf.is_some() || rule.criteria.device_id.is_some() {
+                for field in rule.config_override.remove_vgpud_reported() {
+                    error!(
+                        "rule #{} matches a physical GPU and can not override {} as \
+                         nvidia-vgpud reports the profile without it, ignoring it",
+                        i + 1,
+                        field
+                    );
+                }
+            }
+
+            rules.push(Entry {
+                label: format!("rule #{}", i + 1),
+                path: format!("rule[{}].override", i),
+                rule,
+            });
+        }
+
+        Overrides {
+            rules,
+            dry_run: config.dry_run,
+            sources: BTreeMap::new(),
+            unmatched_reported: Mutex::new(BTreeSet::new()),
+        }
+    }
+}
+
+impl Overrides {
+    /// Returns the rules matching `target` in the order they apply, up to the first matching rule
+    /// that stops further processing. Rules that are a dry run on their own do not stop the rules
+    /// after them.
+    fn matching<'a>(&'a self, target: &'a Target) -> impl Iterator<Item = &'a Entry> {
+        let mut stopped = false;
+
+        self.rules
+            .iter()
+            .filter(move |entry| entry.rule.criteria.matches(target))
+            .take_while(move |entry| {
+                let take = !stopped;
+                stopped = entry.rule.stop && (self.dry_run || !entry.rule.dry_run);
+
+                take
+            })
+    }
+
+    /// Returns the rules matching the vGPU name without wildcards, which match none of `names`.
+    fn unmatched_names<'a>(
+        &'a self,
+        names: &'a BTreeMap<u32, String>,
+    ) -> impl Iterator<Item = &'a Entry> {
+        self.rules.iter().filter(move |entry| {
+            entry.rule.criteria.vgpu_name.as_ref().is_some_and(|name| {
+                !name.contains(['*', '?']) && !names.values().any(|listed| listed == name)
+            })
+        })
+    }
+
+    /// Returns where `key` of the overrides of `entry` was set. For a table, this is where its
+    /// first key was set.
+    fn source(&self, entry: &Entry, key: &str) -> Option<&Source> {
+        let path = layered::key_path(&entry.path, key);
+
+        self.sources.get(&path).or_else(|| {
+            let prefix = format!("{}.", path);
+
+            self.sources
+                .range(prefix.clone()..)
+                .next()
+                .filter(|(key, _)| key.starts_with(&prefix))
+                .map(|(_, source)| source)
+        })
+    }
+}
+
+/// Profile overrides as last parsed, along with the stamps of the files they were parsed from.
+struct CachedOverrides {
+    path: PathBuf,
+    stamps: Vec<FileStamp>,
+    overrides: Arc<Overrides>,
+}
+
+static PROFILE_OVERRIDES: Mutex<Option<CachedOverrides>> = parking_lot::const_mutex(None);
+
+/// The names of the profiles `nvidia-vgpud` listed so far, by vGPU type.
+static LISTED_NAMES: Mutex<BTreeMap<u32, String>> = parking_lot::const_mutex(BTreeMap::new());
+
+fn load_overrides() -> Result<Arc<Overrides>, bool> {
+    let config_path = match env::var_os("VGPU_UNLOCK_PROFILE_OVERRIDE_CONFIG_PATH") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(DEFAULT_PROFILE_OVERRIDE_CONFIG_PATH),
+    };
+
+    load_overrides_from(&config_path)
+}
+
+/// Returns the parsed profile overrides at `config_path`, only parsing the files again when
+/// they changed since the last call.
+fn load_overrides_from(config_path: &Path) -> Result<Arc<Overrides>, bool> {
+    let stamps = match layered::stamps(config_path) {
+        Ok(stamps) => stamps,
+        Err(e) => {
+            error!("{}", e);
+            return Err(false);
+        }
+    };
+
+    if stamps.is_empty() {
+        error!("Config file '{}' not found", config_path.display());
+        return Err(true);
+    }
+
+    let mut cached = PROFILE_OVERRIDES.lock();
+
+    if let Some(cached) = cached.as_ref() {
+        if cached.path == config_path && cached.stamps == stamps {
+            return Ok(cached.overrides.clone());
+        }
+    }
+
+    let layered = match layered::load(
+        config_path,
+        &schema::PROFILE_OVERRIDE,
+        Strictness::from_env(),
+        StrictModes::from_env(),
+    ) {
+        Ok(Some(layered)) => layered,
+        Ok(None) => {
+            error!("Config file '{}' not found", config_path.display());
+            return Err(true);
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(false);
+        }
+    };
+
+    layered.log_sources();
+
+    let overrides = match Value::Table(layered.table).try_into::<ProfileOverridesConfig>() {
+        Ok(config) => Arc::new(Overrides {
+            sources: layered.sources,
+            ..Overrides::from(config)
+        }),
+        Err(e) => {
+            error!("Failed to decode config: {}", e);
+            return Err(false);
+        }
+    };
+
+    *cached = Some(CachedOverrides {
+        path: config_path.to_path_buf(),
+        stamps,
+        overrides: overrides.clone(),
+    });
+
+    Ok(overrides)
+}
+
+pub fn handle_profile_override<C: VgpuConfigLike>(config: &mut C) -> bool {
+    let overrides = match load_overrides() {
+        Ok(overrides) => overrides,
+        Err(e) => return e,
+    };
+
+    let target = Target {
+        vgpu_type_id: *config.vgpu_type(),
+        vgpu_name: utils::from_c_str(config.vgpu_name()).into_owned(),
+        vgpu_class: utils::from_c_str(config.vgpu_class()).into_owned(),
+        mdev: *LAST_MDEV_UUID.lock(),
+        gpu: *LAST_GPU.lock(),
+    };
+    let mut explanation = Explanation::new(format!("nvidia-{}", target.vgpu_type_id), target.mdev);
+
+    if C::LISTS_TYPES {
+        check_listed_name(&overrides, &target);
+    }
+
+    let result = apply_rules(
+        config,
+        &overrides,
+        &target,
+        consistency::Action::from_env(),
+        &mut explanation,
+    );
+
+    explain::publish(&explanation);
+
+    result
+}
+
+/// Records the name of a profile listed by `nvidia-vgpud`. Once a vGPU type is listed again, every
+/// type of the GPUs was listed, and the rules matching a vGPU name without wildcards that is none
+/// of the listed names are logged, once per load of the overrides.
+fn check_listed_name(overrides: &Overrides, target: &Target) {
+    let mut listed = LISTED_NAMES.lock();
+
+    if listed
+        .insert(target.vgpu_type_id, target.vgpu_name.clone())
+        .is_none()
+    {
+        return;
+    }
+
+    let mut reported = overrides.unmatched_reported.lock();
+
+    for entry in overrides.unmatched_names(&listed) {
+        if reported.insert(entry.path.clone()) {
+            let names: Vec<_> = listed.values().map(String::as_str).collect();
+
+            error!(
+                "The vGPU name of {} matches none of the profiles: {}",
+                entry.label,
+                names.join(", ")
+            );
+        }
+    }
+}
+
+/// Applies the rules of `overrides` matching `target` to `config`, and checks the result
+/// according to `action`. Rules that are a dry run are applied to a copy of `config` instead.
+/// The origin of every field changed in `config` is recorded in `explanation`.
+fn apply_rules<C: VgpuConfigLike>(
+    config: &mut C,
+    overrides: &Overrides,
+    target: &Target,
+    action: consistency::Action,
+    explanation: &mut Explanation,
+) -> bool {
+    let vgpu_type = format!("nvidia-{}", target.vgpu_type_id);
+    let mut original_config = config.clone();
+    let original = original_values(config);
+    let mut applied = false;
+
+    let mut scratch;
+    let patched = if overrides.dry_run {
+        scratch = config.clone();
+        &mut scratch
+    } else {
+        config
+    };
+
+    for entry in overrides.matching(target) {
+        let (label, rule) = (&entry.label, &entry.rule);
+        let dry_run = overrides.dry_run || rule.dry_run;
+
+        info!("{}Applying {} overrides", dry_run_tag(dry_run), label);
+
+        if dry_run && !overrides.dry_run {
+            // Try the rule on a copy of the profile as patched by the rules before it.
+            let mut scratch = patched.clone();
+
+            if apply_profile_override(
+                &mut scratch,
+                &vgpu_type,
+                &original,
+                &rule.config_override,
+                true,
+                &mut |_, _, _, _| {},
+            ) {
+                check_consistency(
+                    &mut scratch,
+                    &mut original_config,
+                    &vgpu_type,
+                    action,
+                    true,
+                    &mut Explanation::default(),
+                );
+            } else {
+                info!(
+                    "[dry-run] Overrides of {} would fail the request",
+                    vgpu_type
+                );
+            }
+
+            if rule.stop {
+                info!("[dry-run] Would skip the rules after {}", label);
+            }
+
+            continue;
+        }
+
+        let mut record = |field, key: &str, old, new| {
+            if !dry_run {
+                explanation.record(field, old, new, label, overrides.source(entry, key));
+            }
+        };
+
+        if !apply_profile_override(
+            patched,
+            &vgpu_type,
+            &original,
+            &rule.config_override,
+            dry_run,
+            &mut record,
+        ) {
+            if dry_run {
+                info!(
+                    "[dry-run] Overrides of {} would fail the request",
+                    vgpu_type
+                );
+
+                return true;
+            }
+
+            return false;
+        }
+
+        applied = true;
+
+        if rule.stop {
+            info!("{}Skipping the rules after {}", dry_run_tag(dry_run), label);
+        }
+    }
+
+    if !applied {
+        return true;
+    }
+
+    check_consistency(
+        patched,
+        &mut original_config,
+        &vgpu_type,
+        action,
+        overrides.dry_run,
+        explanation,
+    )
+}
+
+/// Checks `config` after the overrides are applied and handles violations according to
+/// `action`. With `dry_run` the violations are only logged along with what would happen.
+fn check_consistency<C: VgpuConfigLike>(
+    config: &mut C,
+    original_config: &mut C,
+    vgpu_type: &str,
+    action: consistency::Action,
+    dry_run: bool,
+    explanation: &mut Explanation,
+) -> bool {
+    let tag = dry_run_tag(dry_run);
+    let violations = consistency::check(config, original_config);
+
+    for violation in &violations {
+        error!(
+            "{}Overrides of {} are inconsistent, {}",
+            tag, vgpu_type, violation
+        );
+    }
+
+    if violations.is_empty() {
+        return true;
+    }
+
+    match (action, dry_run) {
+        (consistency::Action::Warn, _) => true,
+        (consistency::Action::Reject, false) => {
+            error!("Rejecting the overrides of {}", vgpu_type);
+            *config = original_config.clone();
+            explanation.fields.clear();
+
+            true
+        }
+        (consistency::Action::Reject, true) => {
+            info!("[dry-run] Overrides of {} would be rejected", vgpu_type);
+
+            true
+        }
+        (consistency::Action::Fail, false) => false,
+        (consistency::Action::Fail, true) => {
+            info!(
+                "[dry-run] Overrides of {} would fail the request",
+                vgpu_type
+            );
+
+            true
+        }
+    }
+}
+
+/// Prefix of the log lines of a dry run.
+#[inline]
+fn dry_run_tag(dry_run: bool) -> &'static str {
+    if dry_run {
+        "[dry-run] "
+    } else {
+        ""
+    }
+}
+
+/// Returns the numeric fields of `config` keyed by the name of their override, as referenced by
+/// expressions.
+fn original_values<C: VgpuConfigLike>(config: &mut C) -> BTreeMap<&'static str, u64> {
+    macro_rules! original_values {
+        ($($source_field:ident => $target_field:ident),*$(,)?) => {
+            vec![$((stringify!($source_field), u64::from(*config.$target_field()))),*]
+        };
+    }
+    // Fields only present in some layouts.
+    macro_rules! optional_values {
+        ($($field:ident),*$(,)?) => {
+            vec![$(config.$field().map(|value| (stringify!($field), u64::from(*value)))),*]
+        };
+    }
+
+    let mut values: BTreeMap<_, _> = original_values! {
+        gpu_type => vgpu_type,
+        max_instances => max_instance,
+        num_displays => num_heads,
+        display_width => max_resolution_x,
+        display_height => max_resolution_y,
+        max_pixels => max_pixels,
+        frl_config => frl_config,
+        cuda_enabled => cuda_enabled,
+        ecc_supported => ecc_supported,
+        mig_instance_size => mig_instance_size,
+        multi_vgpu_supported => multi_vgpu_supported,
+        pci_id => vdev_id,
+        pci_device_id => pdev_id,
+        framebuffer => fb_length,
+        mappable_video_size => mappable_video_size,
+        framebuffer_reservation => fb_reservation,
+        encoder_capacity => encoder_capacity,
+        bar1_length => bar1_length,
+        frl_enabled => frl_enable,
+    }
+    .into_iter()
+    .collect();
+
+    values.extend(
+        optional_values! {
+            profile_size,
+            gsp_heap_size,
+            ftrace_enable,
+            gpu_direct_supported,
+            nvlink_p2p_supported,
+            max_instance_per_gi,
+            multi_vgpu_exclusive,
+            exclusive_type,
+            exclusive_size,
+            gpu_instance_profile_id,
+            placement_size,
+        }
+        .into_iter()
+        .flatten(),
+    );
+
+    values
+}
+
+/// Generates placement tables with one placement per instance of `config`. Unless `keep_size`
+/// is set, the placement size is the original one scaled by the change of the framebuffer.
+/// Returns the placement size and the placement ids.
+fn generate_placements<C: VgpuConfigLike>(
+    config: &mut C,
+    original: &BTreeMap<&'static str, u64>,
+    keep_size: bool,
+) -> Result<(u32, Vec<u32>), String> {
+    let placement_size = match config.placement_size() {
+        Some(placement_size) => *placement_size,
+        None => return Err("the placement tables are not present in this profile layout".into()),
+    };
+    let placement_size = if keep_size {
+        placement_size
+    } else {
+        let original_size = u128::from(original["placement_size"]);
+        let original_fb_length = u128::from(original["framebuffer"]);
+
+        if original_size == 0 || original_fb_length == 0 {
+            return Err("the original profile has no placement size to scale".into());
+        }
+
+        let fb_length = u128::from(*config.fb_length());
+        let size = (original_size * fb_length).div_ceil(original_fb_length);
+
+        size.try_into()
+            .map_err(|_| format!("placement size {} is too large", size))?
+    };
+
+    if placement_size == 0 {
+        return Err("the placement size is zero".into());
+    }
+
+    let max_instance = *config.max_instance();
+
+    if max_instance as usize > NVA081_MAX_VGPU_PER_PGPU_V580 {
+        return Err(format!(
+            "{} instances exceed the maximum of {} placements",
+            max_instance, NVA081_MAX_VGPU_PER_PGPU_V580
+        ));
+    }
+
+    let ids = (0..max_instance)
+        .map(|i| i.checked_mul(placement_size))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| {
+            format!(
+                "{} placements of size {} overflow",
+                max_instance, placement_size
+            )
+        })?;
+
+    Ok((placement_size, ids))
+}
+
+/// Returns the fields set by `config_override` that are missing from the layout of `config`.
+///
+/// These fields are only missing from the profiles of `nvidia-vgpu-mgr`, and the same overrides
+/// apply in both daemons, so setting them is reported but does not fail the request.
+fn missing_fields<C: VgpuConfigLike>(
+    config: &mut C,
+    config_override: &VgpuProfileOverride,
+) -> Vec<&'static str> {
+    macro_rules! missing_fields {
+        ($($field:ident),*$(,)?) => {
+            vec![$((
+                stringify!($field),
+                config_override.$field.is_some() && config.$field().is_none(),
+            )),*]
+        };
+    }
+
+    missing_fields! {
+        profile_size,
+        gsp_heap_size,
+        ftrace_enable,
+        gpu_direct_supported,
+        nvlink_p2p_supported,
+        multi_vgpu_exclusive,
+        exclusive_type,
+        exclusive_size,
+        gpu_instance_profile_id,
+    }
+    .into_iter()
+    .filter(|&(_, missing)| missing)
+    .map(|(field, _)| field)
+    .collect()
+}
+
+fn apply_profile_override<C: VgpuConfigLike>(
+    config: &mut C,
+    vgpu_type: &str,
+    original: &BTreeMap<&'static str, u64>,
+    config_override: &VgpuProfileOverride,
+    dry_run: bool,
+    record: &mut dyn FnMut(&'static str, &'static str, String, String),
+) -> bool {
+    let tag = dry_run_tag(dry_run);
+
+    for field in missing_fields(config, config_override) {
+        error!(
+            "{}Patching {}/{}: field is not present in the profiles of nvidia-vgpu-mgr, set it in \
+             a [process.nvidia-vgpud] section instead",
+            tag, vgpu_type, field
+        );
+    }
+
+    macro_rules! patch_msg {
+        ($source_field:ident, $target_field:ident, $value:expr) => {
+            patch_msg!(@record $source_field, $target_field, config.$target_field(), $value);
+        };
+        ($source_field:ident, $target_field:ident, $preprocess:expr, $value:expr) => {
+            patch_msg!(
+                @record $source_field,
+                $target_field,
+                $preprocess(config.$target_field()),
+                $value
+            );
+        };
+        (@record $source_field:ident, $target_field:ident, $old:expr, $value:expr) => {
+            let old = $old.to_string();
+            let new = format!("{}", $value);
+
+            info!(
+                "{}Patching {}/{}: {} -> {}",
+                tag,
+                vgpu_type,
+                stringify!($target_field),
+                old,
+                new
+            );
+
+            record(stringify!($target_field), stringify!($source_field), old, new);
+        };
+    }
+    macro_rules! eval_expr {
+        ($value:ident, $source_field:ident, $target_field:ident) => {
+            match $value
+                .eval(original[stringify!($source_field)], &|name| {
+                    original.get(name).copied()
+                })
+                .and_then(|value| value.try_into().map_err(|_| EvalError::Overflow))
+            {
+                Ok(value) => value,
+                Err(e) => {
+                    error!(
+                        "{}Patching {}/{}: {}",
+                        tag,
+                        vgpu_type,
+                        stringify!($target_field),
+                        e
+                    );
+
+                    return false;
+                }
+            }
+        };
+    }
+    macro_rules! patch_ids {
+        ($source_field:ident, $target_field:ident, $ids:expr) => {
+            match config.$target_field() {
+                Some((count, table)) => {
+                    let ids: &[u32] = $ids;
+                    let old = format!("{:?}", &table[..cmp::min(*count as usize, table.len())]);
+
+                    patch_msg!(@record $source_field, $target_field, old, format!("{:?}", ids));
+
+                    // Zero out the table first.
+                    for v in table.iter_mut() {
+                        *v = 0;
+                    }
+
+                    table[..ids.len()].copy_from_slice(ids);
+                    *count = ids.len() as u32;
+                }
+                None => placement_not_present!($target_field),
+            }
+        };
+    }
+    // The placement fields are missing from both daemons on drivers before R580, so setting them
+    // is never valid there.
+    macro_rules! placement_not_present {
+        ($target_field:ident) => {{
+            error!(
+                "{}Patching {}/{}: field is not present in this profile layout",
+                tag,
+                vgpu_type,
+                stringify!($target_field)
+            );
+
+            return false;
+        }};
+    }
+    macro_rules! error_too_long {
+        ($target_field:ident, $value:expr) => {
+            error!(
+                "{}Patching {}/{}: value '{}' is too long",
+                tag,
+                vgpu_type,
+                stringify!($target_field),
+                $value
+            );
+
+            return false;
+        };
+    }
+
+    macro_rules! handle_override {
+        // Override entrypoint when the same field name is used as the source and target without
+        // an explicit `=>`.
+        (
+            class: $class:ident,
+            source_field: $field:ident,
+        ) => {
+            handle_override! {
+                class: $class,
+                source_field: $field,
+                target_field: $field,
+            }
+        };
+
+        // Override entrypoint when both the source and target field names are defined explicitly.
+        (
+            class: $class:ident,
+            source_field: $source_field:ident,
+            target_field: $target_field:ident,
+        ) => {
+            if let Some(value) = config_override.$source_field.as_ref() {
+                handle_override! {
+                    class: $class,
+                    value: value,
+                    source_field: $source_field,
+                    target_field: $target_field,
+                }
+            }
+        };
+
+        // The following are override handlers for each field class type (`bool`, `copy`, `str`,
+        // and `wide_str`).
+        (
+            class: bool,
+            value: $value:ident,
+            source_field: $source_field:ident,
+            target_field: $target_field:ident,
+        ) => {
+            let $value = cmp::max(cmp::min(*$value, 1), 0);
+
+            patch_msg!($source_field, $target_field, $value);
+
+            *config.$target_field() = $value;
+        };
+        (
+            class: copy,
+            value: $value:ident,
+            source_field: $source_field:ident,
+            target_field: $target_field:ident,
+        ) => {
+            let $value = eval_expr!($value, $source_field, $target_field);
+
+            patch_msg!($source_field, $target_field, $value);
+
+            *config.$target_field() = $value;
+        };
+        (
+            class: optional,
+            value: $value:ident,
+            source_field: $source_field:ident,
+            target_field: $target_field:ident,
+        ) => {
+            match config.$target_field().map(|value| *value) {
+                Some(old) => {
+                    let $value = eval_expr!($value, $source_field, $target_field);
+
+                    patch_msg!(@record $source_field, $target_field, old, $value);
+
+                    if let Some(value) = config.$target_field() {
+                        *value = $value;
+                    }
+                }
+                // Reported by `missing_fields`.
+                None => {}
+            }
+        };
+        (
+            class: placement,
+            value: $value:ident,
+            source_field: $source_field:ident,
+            target_field: $target_field:ident,
+        ) => {
+            if config.$target_field().is_none() {
+                placement_not_present!($target_field);
+            }
+
+            handle_override! {
+                class: optional,
+                value: $value,
+                source_field: $source_field,
+                target_field: $target_field,
+            }
+        };
+        (
+            class: optional_bool,
+            value: $value:ident,
+            source_field: $source_field:ident,
+            target_field: $target_field:ident,
+        ) => {
+            let $value = cmp::max(cmp::min(*$value, 1), 0);
+
+            match config.$target_field() {
+                Some(field) => {
+                    patch_msg!(@record $source_field, $target_field, *field, $value);
+
+                    *field = $value;
+                }
+                // Reported by `missing_fields`.
+                None => {}
+            }
+        };
+        (
+            class: extra_params,
+            value: $value:ident,
+            source_field: $source_field:ident,
+            target_field: $target_field:ident,
+        ) => {
+            let old = format!("{:?}", config.$target_field());
+
+            for (key, &param) in $value.iter() {
+                let index = match extra_params::index(key) {
+                    Some(index) => index,
+                    None => {
+                        error!(
+                            "{}Patching {}/{}: '{}' is not a parameter index",
+                            tag,
+                            vgpu_type,
+                            stringify!($target_field),
+                            key
+                        );
+
+                        return false;
+                    }
+                };
+
+                let mut params = config.$target_field();
+                let current = params.get(index).unwrap_or(0);
+
+                match params.set(index, param) {
+                    Ok(()) => {
+                        info!(
+                            "{}Patching {}/{}[{}]: {:#x} -> {:#x}",
+                            tag,
+                            vgpu_type,
+                            stringify!($target_field),
+                            index,
+                            current,
+                            param
+                        );
+                    }
+                    // Only the bytes of `nvidia-vgpu-mgr` are too small for a value, which fits
+                    // the words of `nvidia-vgpud`, so the parameter is skipped instead of failing
+                    // the request.
+                    Err(e @ SetError::TooLarge { .. }) => {
+                        error!(
+                            "{}Patching {}/{}[{}]: {} in the profiles of nvidia-vgpu-mgr, set it \
+                             in a [process.nvidia-vgpud] section instead",
+                            tag,
+                            vgpu_type,
+                            stringify!($target_field),
+                            index,
+                            e
+                        );
+                    }
+                    Err(e) => {
+                        error!(
+                            "{}Patching {}/{}[{}]: {}",
+                            tag,
+                            vgpu_type,
+                            stringify!($target_field),
+                            index,
+                            e
+                        );
+
+                        return false;
+                    }
+                }
+            }
+
+            let new = format!("{:?}", config.$target_field());
+
+            record(stringify!($target_field), stringify!($source_field), old, new);
+        };
+        (
+            class: ids,
+            value: $value:ident,
+            source_field: $source_field:ident,
+            target_field: $target_field:ident,
+        ) => {
+            if $value.len() > NVA081_MAX_VGPU_PER_PGPU_V580 {
+                error!(
+                    "{}Patching {}/{}: {} placements exceed the maximum of {}",
+                    tag,
+                    vgpu_type,
+                    stringify!($target_field),
+                    $value.len(),
+                    NVA081_MAX_VGPU_PER_PGPU_V580
+                );
+
+                return false;
+            }
+
+            patch_ids!($source_field, $target_field, &$value[..]);
+        };
+        (
+            class: str,
+            value: $value:ident,
+            source_field: $source_field:ident,
+            target_field: $target_field:ident,
+        ) => {
+            let value_bytes = $value.as_bytes();
+
+            // Use `len - 1` to account for the required NULL terminator.
+            if value_bytes.len() > config.$target_field().len() - 1 {
+                error_too_long!($target_field, $value);
+            } else {
+                patch_msg!($source_field, $target_field, utils::from_c_str, $value);
+
+                // Zero out the field first.
+                config.$target_field().fill(0);
+
+                // Write the string bytes.
+                let _ = config.$target_field()[..].as_mut().write_all(value_bytes);
+            }
+        };
+        (
+            class: wide_str,
+            value: $value:ident,
+            source_field: $source_field:ident,
+            target_field: $target_field:ident,
+        ) => {
+            // Use `len - 1` to account for the required NULL terminator.
+            if $value.encode_utf16().count() > config.$target_field().len() - 1 {
+                error_too_long!($target_field, $value);
+            } else {
+                patch_msg!($source_field, $target_field, WideCharFormat, $value);
+
+                // Zero out the field first.
+                config.$target_field().fill(0);
+
+                // Write the string bytes.
+                for (v, ch) in config.$target_field()[..]
+                    .iter_mut()
+                    .zip($value.encode_utf16().chain(Some(0)))
+                {
+                    *v = ch;
+                }
+            }
+        };
+    }
+    macro_rules! handle_overrides {
+        (
+            $($class:ident: [
+                $($source_field:ident $(=> $target_field:ident)?),*$(,)?
+            ]),*$(,)?
+        ) => {
+            $(
+                $(
+                    handle_override! {
+                        class: $class,
+                        source_field: $source_field,
+                        $(target_field: $target_field,)?
+                    }
+                )*
+            )*
+        };
+    }
+
+    // While the following could be done with fewer branches, I wanted the log statements to be in
+    // field order.
+
+    handle_overrides! {
+        copy: [
+            gpu_type => vgpu_type,
+        ],
+        str: [
+            card_name => vgpu_name,
+            vgpu_type => vgpu_class,
+            features => license,
+        ],
+        copy: [
+            max_instances => max_instance,
+            num_displays => num_heads,
+            display_width => max_resolution_x,
+            display_height => max_resolution_y,
+        ],
+    }
+
+    if let Some(resolution) = config_override.resolution {
+        for key in config_override.resolution_conflicts() {
+            error!(
+                "{}Patching {}: {} conflicts with resolution {}, using the resolution",
+                tag, vgpu_type, key, resolution
+            );
+        }
+
+        patch_msg!(resolution, max_resolution_x, resolution.width);
+        *config.max_resolution_x() = resolution.width;
+
+        patch_msg!(resolution, max_resolution_y, resolution.height);
+        *config.max_resolution_y() = resolution.height;
+
+        if config_override.max_pixels.is_none() {
+            let num_heads = *config.num_heads();
+            let max_pixels: Option<u32> = resolution
+                .pixels()
+                .checked_mul(u64::from(num_heads))
+                .and_then(|max_pixels| max_pixels.try_into().ok());
+
+            match max_pixels {
+                Some(max_pixels) => {
+                    patch_msg!(resolution, max_pixels, max_pixels);
+                    *config.max_pixels() = max_pixels;
+                }
+                None => {
+                    error!(
+                        "{}Patching {}/max_pixels: {} displays at {} is too large",
+                        tag, vgpu_type, num_heads, resolution
+                    );
+
+                    return false;
+                }
+            }
+        }
+    }
+
+    handle_overrides! {
+        copy: [
+            max_pixels,
+            frl_config,
+        ],
+        bool: [
+            cuda_enabled,
+            ecc_supported,
+        ],
+        copy: [
+            mig_instance_size,
+        ],
+        bool: [
+            multi_vgpu_supported,
+        ],
+        copy: [
+            pci_id => vdev_id,
+            pci_device_id => pdev_id,
+        ],
+        optional: [
+            profile_size,
+        ],
+        copy: [
+            framebuffer => fb_length,
+        ],
+        optional: [
+            gsp_heap_size,
+        ],
+        copy: [
+            mappable_video_size,
+            framebuffer_reservation => fb_reservation,
+            encoder_capacity,
+            bar1_length,
+        ],
+        bool: [
+            frl_enabled => frl_enable,
+        ],
+        str: [
+            adapter_name,
+        ],
+    }
+
+    // `adapter_name_unicode` follows `adapter_name` unless it is set on its own.
+    if config_override.adapter_name_unicode.is_some() {
+        handle_overrides! {
+            wide_str: [
+                adapter_name_unicode,
+            ],
+        }
+    } else {
+        handle_overrides! {
+            wide_str: [
+                adapter_name => adapter_name_unicode,
+            ],
+        }
+    }
+
+    handle_overrides! {
+        str: [
+            short_gpu_name => short_gpu_name_string,
+            license_type => licensed_product_name,
+        ],
+        extra_params: [
+            extra_params => vgpu_extra_params,
+        ],
+        optional_bool: [
+            ftrace_enable,
+            gpu_direct_supported,
+            nvlink_p2p_supported,
+        ],
+        placement: [
+            max_instance_per_gi,
+        ],
+        optional_bool: [
+            multi_vgpu_exclusive,
+        ],
+        optional: [
+            exclusive_type,
+            exclusive_size,
+            gpu_instance_profile_id,
+        ],
+        placement: [
+            placement_size,
+        ],
+        ids: [
+            homogeneous_placement_ids,
+            heterogeneous_placement_ids,
+        ],
+    }
+
+    if config_override.regenerate_placements {
+        let keep_size = config_override.placement_size.is_some();
+
+        match generate_placements(config, original, keep_size) {
+            Ok((placement_size, ids)) => {
+                if let Some(size) = config.placement_size() {
+                    patch_msg!(@record regenerate_placements, placement_size, *size, placement_size);
+                    *size = placement_size;
+                }
+
+                patch_ids!(regenerate_placements, homogeneous_placement_ids, &ids[..]);
+                patch_ids!(regenerate_placements, heterogeneous_placement_ids, &ids[..]);
+            }
+            Err(e) => {
+                error!("{}Regenerating the placements of {}: {}", tag, vgpu_type, e);
+
+                return false;
+            }
+        }
+    }
+
+    if let Some(resolution) = config_override.resolution {
+        if config_override.max_pixels.is_some()
+            && u64::from(*config.max_pixels()) < resolution.pixels()
+        {
+            error!(
+                "{}Patching {}/max_pixels: {} is too small for a single display at {}",
+                tag,
+                vgpu_type,
+                config.max_pixels(),
+                resolution
+            );
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::env;
+    use std::fs;
+    use std::mem;
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use toml::Value;
+
+    use super::{
+        apply_profile_override, apply_rules, layered, load_overrides_from, missing_fields,
+        original_values, Criteria, Group, Overrides, ProfileOverridesConfig, Rule, Target,
+        VgpuProfileOverride,
+    };
+    use crate::consistency::Action;
+    use crate::explain::Explanation;
+    use crate::extra_params::ExtraParamsFormat;
+    use crate::format::WideCharFormat;
+    use crate::human_number::Expr;
+    use crate::layered::Source;
+    use crate::nvidia::ctrla081::{NvA081CtrlVgpuInfoV525, NvA081CtrlVgpuInfoV580};
+    use crate::nvidia::ctrla082::NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525;
+    use crate::pci::{PciBdf, PhysicalGpu};
+    use crate::permissions::StrictModes;
+    use crate::schema::{self, PROFILE_OVERRIDE as SCHEMA};
+    use crate::utils;
+    use crate::uuid::Uuid;
+    use crate::validate::{self, Strictness};
+    use crate::VgpuConfigLike;
+
+    const OVERRIDES: &str = r#"
+[profile.nvidia-55]
+num_displays = 1
+display_width = 1920
+display_height = 1080
+max_pixels = 2073600
+cuda_enabled = 1
+frl_enabled = 0
+
+[profile.nvidia-259]
+framebuffer = "2GiB"
+adapter_name = "GRID RTX6000-2Q"
+
+[mdev.00000000-0000-0000-0000-000000000100]
+frl_enabled = 1
+
+[process.nvidia-vgpu-mgr.profile.nvidia-259]
+cuda_enabled = 1
+"#;
+
+    fn temp_overrides(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("vgpu_unlock-test-{}-{}", name, process::id()));
+
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("profile_override.toml");
+        fs::write(&path, OVERRIDES).unwrap();
+
+        path
+    }
+
+    fn load_uncached(path: &Path) -> ProfileOverridesConfig {
+        let layered = layered::load(path, &SCHEMA, Strictness::Lenient, StrictModes::Yes)
+            .ok()
+            .unwrap()
+            .unwrap();
+
+        Value::Table(layered.table).try_into().unwrap()
+    }
+
+    #[test]
+    fn test_schema_fields() {
+        assert_eq!(
+            schema::PROFILE_OVERRIDES_FIELDS,
+            validate::fields::<ProfileOverridesConfig>()
+        );
+        assert_eq!(schema::GROUP_FIELDS, validate::fields::<Group>());
+        assert_eq!(schema::RULE_FIELDS, validate::fields::<Rule>());
+        assert_eq!(schema::CRITERIA_FIELDS, validate::fields::<Criteria>());
+        assert_eq!(
+            schema::VGPU_PROFILE_OVERRIDE_FIELDS,
+            validate::fields::<VgpuProfileOverride>()
+        );
+    }
+
+    #[test]
+    fn test_load_overrides_cached() {
+        let path = temp_overrides("cache");
+
+        let first = load_overrides_from(&path).unwrap();
+        let second = load_overrides_from(&path).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        fs::write(&path, format!("{}\n[profile.nvidia-56]\n", OVERRIDES)).unwrap();
+
+        let third = load_overrides_from(&path).unwrap();
+        assert!(!Arc::ptr_eq(&second, &third));
+        assert!(third
+            .rules
+            .iter()
+            .any(|entry| entry.label == "profile nvidia-56"));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    /// Returns the labels of the rules in `data` that apply to `target`.
+    fn matching(data: &str, target: &Target) -> Vec<String> {
+        let config: ProfileOverridesConfig = toml::from_str(data).unwrap();
+        let overrides = Overrides::from(config);
+
+        overrides
+            .matching(target)
+            .map(|entry| entry.label.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        let data = r#"
+[profile.nvidia-259]
+[profile_name."GRID RTX6000-*Q"]
+[profile_name."GRID RTX6000-*A"]
+[profile_name."*"]
+[gpu."41:00.0"]
+[gpu."0000:01:00.0"]
+[gpu.invalid]
+[group.other]
+mdevs = ["00000000-0000-0000-0000-000000000100"]
+[mdev.00000000-0000-0000-0000-000000000100]
+
+[[rule]]
+match = { vgpu_class = "Quad*", gpu_bdf = "0000:41:00.0" }
+
+[[rule]]
+match = { device_id = 0x1e30, vmid = 100 }
+override = { framebuffer = "2GiB" }
+stop = true
+
+[[rule]]
+"#;
+        let mut target = Target {
+            vgpu_type_id: 259,
+            vgpu_name: "GRID RTX6000-2Q".to_string(),
+            vgpu_class: "Quadro".to_string(),
+            mdev: Some(Uuid(0, 0, 0, [0, 0, 0, 0, 0, 0, 0x01, 0x00])),
+            gpu: Some(PhysicalGpu {
+                bdf: PciBdf(0x4100),
+                pci_id: 0x1e30_10de,
+            }),
+        };
+
+        assert_eq!(
+            matching(data, &target),
+            vec![
+                "profile name \"*\"",
+                "profile name \"GRID RTX6000-*Q\"",
+                "profile nvidia-259",
+                "GPU 0000:41:00.0",
+                "group other",
+                "mdev UUID 00000000-0000-0000-0000-000000000100",
+                "rule #1",
+                "rule #2",
+            ]
+        );
+
+        target.vgpu_type_id = 55;
+        target.gpu = None;
+        assert_eq!(
+            matching(data, &target),
+            vec![
+                "profile name \"*\"",
+                "profile name \"GRID RTX6000-*Q\"",
+                "group other",
+                "mdev UUID 00000000-0000-0000-0000-000000000100",
+                "rule #3",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unmatched_names() {
+        let data = r#"
+[profile_name."GRID RTX6000-4Q"]
+[profile_name."GRID RTX6000-4q"]
+[profile_name."GRID RTX6000-*"]
+
+[[rule]]
+match = { vgpu_name = "GRID RTX6000-8A" }
+"#;
+        let overrides = Overrides::from(toml::from_str::<ProfileOverridesConfig>(data).unwrap());
+        let names = BTreeMap::from([
+            (259, "GRID RTX6000-4Q".to_string()),
+            (260, "GRID RTX6000-8Q".to_string()),
+        ]);
+        let unmatched: Vec<_> = overrides
+            .unmatched_names(&names)
+            .map(|entry| entry.label.as_str())
+            .collect();
+
+        assert_eq!(unmatched, ["profile name \"GRID RTX6000-4q\"", "rule #1"]);
+    }
+
+    #[test]
+    fn test_invalid_profile_keys() {
+        let invalid_keys: Vec<_> = validate::check(
+            "[profile.nvidia-55]\n[profile.nvidia55]\n[profile.\"nvdia-55\"]\n\
+             [profile.\"GRID RTX6000-*Q\"]\n[profile_name.\"GRID RTX6000-*Q\"]\n",
+            &SCHEMA,
+        )
+        .invalid
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+
+        assert_eq!(
+            invalid_keys,
+            [
+                "line 2: invalid key `profile.nvidia55`, expected a vGPU type such as \
+                 `nvidia-55`, or a `[profile_name]` table",
+                "line 3: invalid key `profile.nvdia-55`, expected a vGPU type such as \
+                 `nvidia-55`, or a `[profile_name]` table",
+                "line 4: invalid key `profile.\"GRID RTX6000-*Q\"`, expected a vGPU type such as \
+                 `nvidia-55`, or a `[profile_name]` table",
+            ]
+        );
+    }
+
+    #[cfg(feature = "proxmox")]
+    #[test]
+    fn test_vm_rules() {
+        let data = r#"
+[group.cad]
+vmids = [256, "300-399"]
+[group.other]
+mdevs = ["00000000-0000-0000-0000-000000000200"]
+[vm.256]
+[vm."200-299"]
+[vm."0-999"]
+[vm."999-0"]
+
+[[rule]]
+match = { vmid = "250-260" }
+"#;
+        let mut target = Target {
+            vgpu_type_id: 259,
+            vgpu_name: "GRID RTX6000-2Q".to_string(),
+            vgpu_class: "Quadro".to_string(),
+            mdev: Some(Uuid(0, 0, 0, [0, 0, 0, 0, 0, 0, 0x02, 0x56])),
+            gpu: None,
+        };
+
+        assert_eq!(
+            matching(data, &target),
+            vec![
+                "group cad",
+                "proxmox VMIDs 0-999",
+                "proxmox VMIDs 200-299",
+                "proxmox VMID 256",
+                "rule #1",
+            ]
+        );
+
+        target.mdev = Some(Uuid(0, 0, 0, [0, 0, 0, 0, 0, 0, 0x03, 0x50]));
+        assert_eq!(
+            matching(data, &target),
+            vec!["group cad", "proxmox VMIDs 0-999"]
+        );
+
+        target.mdev = Some(Uuid(0, 0, 0, [0, 0, 0, 0, 0, 0, 0x02, 0x00]));
+        assert_eq!(
+            matching(data, &target),
+            vec![
+                "group other",
+                "proxmox VMIDs 0-999",
+                "proxmox VMIDs 200-299"
+            ]
+        );
+    }
+
+    /// Applies the override `data` to `params` the way a single rule for `nvidia-55` would, with
+    /// the expressions evaluated against the `original` values of the profile.
+    fn apply<C: VgpuConfigLike>(
+        params: &mut C,
+        original: &BTreeMap<&'static str, u64>,
+        data: &str,
+    ) -> bool {
+        let config_override: VgpuProfileOverride = toml::from_str(data).unwrap();
+
+        apply_profile_override(
+            params,
+            "nvidia-55",
+            original,
+            &config_override,
+            false,
+            &mut |_, _, _, _| {},
+        )
+    }
+
+    #[test]
+    fn test_expressions() {
+        let mut params: NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 =
+            unsafe { mem::zeroed() };
+        params.max_instance = 4;
+        params.max_resolution_x = 1920;
+        params.max_resolution_y = 1080;
+        params.fb_length = 1 << 30;
+
+        let original = original_values(&mut params);
+
+        assert!(apply(
+            &mut params,
+            &original,
+            "framebuffer = \"+512MiB\"\nmax_instances = \"*2\"\n\
+             max_pixels = \"display_width*display_height\"\ndisplay_width = 3840\n"
+        ));
+        assert_eq!(params.fb_length, 1536 << 20);
+        assert_eq!(params.max_instance, 8);
+        assert_eq!(params.max_pixels, 1920 * 1080);
+        assert_eq!(params.max_resolution_x, 3840);
+
+        // Relative to the original value, not the one set by an earlier rule.
+        assert!(apply(&mut params, &original, "max_instances = \"*2\"\n"));
+        assert_eq!(params.max_instance, 8);
+
+        assert!(!apply(&mut params, &original, "max_instances = \"-5\"\n"));
+        assert!(!apply(
+            &mut params,
+            &original,
+            "max_pixels = \"display_width * 0x100000000\"\n"
+        ));
+        assert!(!apply(
+            &mut params,
+            &original,
+            "max_pixels = \"display_depth\"\n"
+        ));
+        assert_eq!(params.max_instance, 8);
+        assert_eq!(params.max_pixels, 1920 * 1080);
+    }
+
+    #[test]
+    fn test_optional_fields() {
+        let data = "profile_size = \"4GiB\"\ngsp_heap_size = \"+32MiB\"\nframebuffer = \"3GiB\"\n\
+                    multi_vgpu_exclusive = 2\nexclusive_type = 1\nexclusive_size = \"+1\"\n\
+                    ftrace_enable = 1\nnvlink_p2p_supported = 0\n\
+                    gpu_instance_profile_id = 0x10\n";
+
+        let mut info: NvA081CtrlVgpuInfoV525 = unsafe { mem::zeroed() };
+        info.gsp_heap_size.0 = 64 << 20;
+        info.exclusive_size = 2;
+        info.gpu_direct_supported = 1;
+        info.nvlink_p2p_supported = 1;
+
+        let original = original_values(&mut info);
+        assert!(apply(&mut info, &original, data));
+        assert_eq!(info.profile_size.0, 4 << 30);
+        assert_eq!(info.gsp_heap_size.0, 96 << 20);
+        assert_eq!(info.fb_length.0, 3 << 30);
+        assert_eq!(info.multi_vgpu_exclusive, 1);
+        assert_eq!(info.ftrace_enable, 1);
+        assert_eq!(info.nvlink_p2p_supported, 0);
+        assert_eq!(info.gpu_direct_supported, 1);
+        assert_eq!(info.exclusive_type, 1);
+        assert_eq!(info.exclusive_size, 3);
+        assert_eq!(info.gpu_instance_profile_id, 0x10);
+
+        // The same overrides apply in nvidia-vgpu-mgr, whose layout reports the fields it lacks
+        // as errors and applies the others.
+        let mut params: NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 =
+            unsafe { mem::zeroed() };
+        let original = original_values(&mut params);
+        let gsp_heap_size: VgpuProfileOverride =
+            toml::from_str("gsp_heap_size = \"+32MiB\"\nframebuffer = \"3GiB\"\n").unwrap();
+
+        assert_eq!(
+            missing_fields(&mut params, &gsp_heap_size),
+            vec!["gsp_heap_size"]
+        );
+        assert_eq!(
+            missing_fields(&mut info, &gsp_heap_size),
+            Vec::<&str>::new()
+        );
+        assert!(apply(&mut params, &original, data));
+        assert_eq!(params.fb_length, 3 << 30);
+
+        let exclusive = "multi_vgpu_exclusive = 1\nexclusive_type = 1\nexclusive_size = \"+1\"\n\
+                         gpu_instance_profile_id = 0x10\n";
+        assert_eq!(
+            missing_fields(&mut params, &toml::from_str(exclusive).unwrap()),
+            vec![
+                "multi_vgpu_exclusive",
+                "exclusive_type",
+                "exclusive_size",
+                "gpu_instance_profile_id"
+            ]
+        );
+        assert!(apply(&mut params, &original, exclusive));
+
+        let flags = "ftrace_enable = 1\ngpu_direct_supported = 1\nnvlink_p2p_supported = 0\n";
+        assert_eq!(
+            missing_fields(&mut params, &toml::from_str(flags).unwrap()),
+            vec![
+                "ftrace_enable",
+                "gpu_direct_supported",
+                "nvlink_p2p_supported"
+            ]
+        );
+        assert!(apply(&mut params, &original, flags));
+    }
+
+    #[test]
+    fn test_extra_params() {
+        let data = "extra_params = { 3 = 0x1, 0x11 = 0x140 }\n";
+
+        let mut info: NvA081CtrlVgpuInfoV525 = unsafe { mem::zeroed() };
+        let original = original_values(&mut info);
+        assert!(apply(&mut info, &original, data));
+        assert_eq!(
+            format!("{:?}", ExtraParamsFormat(&info.vgpu_extra_params[..])),
+            "{3: 0x1, 17: 0x140}"
+        );
+
+        assert!(!apply(
+            &mut info,
+            &original,
+            "extra_params = { 1024 = 1 }\n"
+        ));
+        assert!(!apply(
+            &mut info,
+            &original,
+            "extra_params = { unknown = 1 }\n"
+        ));
+
+        // The parameters are bytes in this layout, so 0x140 does not fit and is skipped.
+        let mut params: NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 =
+            unsafe { mem::zeroed() };
+        let original = original_values(&mut params);
+        assert!(apply(&mut params, &original, data));
+        assert_eq!(
+            format!("{:?}", ExtraParamsFormat(&params.vgpu_extra_params[..])),
+            "{3: 0x1}"
+        );
+        assert!(apply(
+            &mut params,
+            &original,
+            "extra_params = { 3 = 0x1, 0x11 = 0x40 }\n"
+        ));
+        assert_eq!(
+            format!("{:?}", ExtraParamsFormat(&params.vgpu_extra_params[..])),
+            "{3: 0x1, 17: 0x40}"
+        );
+    }
+
+    #[test]
+    fn test_adapter_name_unicode() {
+        let mut params: NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 =
+            unsafe { mem::zeroed() };
+
+        let original = original_values(&mut params);
+        let unicode = |params: &NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525| {
+            WideCharFormat(&params.adapter_name_unicode).to_string()
+        };
+
+        assert!(apply(
+            &mut params,
+            &original,
+            "adapter_name = \"GRID P40\"\n"
+        ));
+        assert_eq!(unicode(&params), "GRID P40");
+
+        assert!(apply(
+            &mut params,
+            &original,
+            "adapter_name = \"GRID P40-2Q\"\nadapter_name_unicode = \"GRID P40 – 2Q 🖥\"\n"
+        ));
+        assert_eq!(utils::from_c_str(&params.adapter_name), "GRID P40-2Q");
+        assert_eq!(unicode(&params), "GRID P40 – 2Q 🖥");
+
+        assert!(apply(
+            &mut params,
+            &original,
+            "adapter_name_unicode = \"Ünicode\"\n"
+        ));
+        assert_eq!(utils::from_c_str(&params.adapter_name), "GRID P40-2Q");
+        assert_eq!(unicode(&params), "Ünicode");
+
+        // 32 characters outside of the BMP take 64 UTF-16 code units, leaving no room for the
+        // terminator.
+        let data = format!("adapter_name_unicode = \"{}\"\n", "🖥".repeat(32));
+        assert!(!apply(&mut params, &original, &data));
+        assert_eq!(unicode(&params), "Ünicode");
+    }
+
+    #[test]
+    fn test_placements() {
+        let mut info: NvA081CtrlVgpuInfoV580 = unsafe { mem::zeroed() };
+        info.max_instance = 4;
+        info.fb_length.0 = 4 << 30;
+        info.placement_size = 4;
+        info.homogeneous_placement_count = 4;
+        info.homogeneous_placement_ids[..4].copy_from_slice(&[0, 4, 8, 12]);
+
+        let original = original_values(&mut info);
+
+        assert!(apply(
+            &mut info,
+            &original,
+            "homogeneous_placement_ids = [0, 8]\nmax_instance_per_gi = 2\n"
+        ));
+        assert_eq!(info.homogeneous_placement_count, 2);
+        assert_eq!(&info.homogeneous_placement_ids[..3], &[0, 8, 0]);
+        assert_eq!(info.max_instance_per_gi, 2);
+
+        let too_many: Vec<_> = (0..49).map(|i| i.to_string()).collect();
+        assert!(!apply(
+            &mut info,
+            &original,
+            &format!("heterogeneous_placement_ids = [{}]\n", too_many.join(", "))
+        ));
+        assert_eq!(info.heterogeneous_placement_count, 0);
+
+        assert!(apply(
+            &mut info,
+            &original,
+            "framebuffer = \"/2\"\nmax_instances = \"*2\"\nregenerate_placements = true\n"
+        ));
+        assert_eq!(info.placement_size, 2);
+        assert_eq!(info.homogeneous_placement_count, 8);
+        assert_eq!(
+            &info.homogeneous_placement_ids[..9],
+            &[0, 2, 4, 6, 8, 10, 12, 14, 0]
+        );
+        assert_eq!(
+            &info.heterogeneous_placement_ids[..8],
+            &info.homogeneous_placement_ids[..8]
+        );
+
+        // An explicit placement size is kept.
+        assert!(apply(
+            &mut info,
+            &original,
+            "placement_size = 3\nregenerate_placements = true\n"
+        ));
+        assert_eq!(&info.homogeneous_placement_ids[..3], &[0, 3, 6]);
+
+        assert!(!apply(
+            &mut info,
+            &original,
+            "max_instances = 49\nregenerate_placements = true\n"
+        ));
+
+        // The placement fields are missing from the profiles of both daemons before R580.
+        let mut info: NvA081CtrlVgpuInfoV525 = unsafe { mem::zeroed() };
+        let original = original_values(&mut info);
+
+        assert!(!apply(&mut info, &original, "max_instance_per_gi = 2\n"));
+        assert!(!apply(&mut info, &original, "placement_size = 2\n"));
+
+        let mut params: NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 =
+            unsafe { mem::zeroed() };
+        let original = original_values(&mut params);
+
+        assert!(!apply(&mut params, &original, "max_instance_per_gi = 2\n"));
+        assert!(!apply(
+            &mut params,
+            &original,
+            "homogeneous_placement_ids = [0]\n"
+        ));
+    }
+
+    #[test]
+    fn test_resolution() {
+        let mut params: NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 =
+            unsafe { mem::zeroed() };
+        params.num_heads = 4;
+
+        let original = original_values(&mut params);
+
+        assert!(apply(&mut params, &original, "resolution = \"4k\"\n"));
+        assert_eq!(params.max_resolution_x, 3840);
+        assert_eq!(params.max_resolution_y, 2160);
+        assert_eq!(params.max_pixels, 4 * 3840 * 2160);
+
+        assert!(apply(
+            &mut params,
+            &original,
+            "resolution = \"1920x1080\"\nnum_displays = 1\n"
+        ));
+        assert_eq!(params.max_pixels, 1920 * 1080);
+
+        // An explicit value is kept, even when it is too small.
+        assert!(apply(
+            &mut params,
+            &original,
+            "resolution = \"2560x1600\"\nmax_pixels = 2073600\n"
+        ));
+        assert_eq!(params.max_resolution_x, 2560);
+        assert_eq!(params.max_pixels, 2073600);
+
+        assert!(!apply(
+            &mut params,
+            &original,
+            "resolution = \"65535x65535\"\nnum_displays = 4\n"
+        ));
+
+        // The resolution wins over the display size set along with it, which is reported.
+        let conflicting = "resolution = \"4k\"\ndisplay_width = 1280\nnum_displays = 1\n";
+        let config_override: VgpuProfileOverride = toml::from_str(conflicting).unwrap();
+        assert_eq!(config_override.resolution_conflicts(), ["display_width"]);
+        assert!(apply(&mut params, &original, conflicting));
+        assert_eq!(params.max_resolution_x, 3840);
+
+        let config_override: VgpuProfileOverride =
+            toml::from_str("display_width = 1280\ndisplay_height = 720\n").unwrap();
+        assert!(config_override.resolution_conflicts().is_empty());
+    }
+
+    #[test]
+    fn test_consistency_action() {
+        let mut params: NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 =
+            unsafe { mem::zeroed() };
+        params.vgpu_type = 55;
+        params.max_instance = 12;
+        params.fb_length = 2 << 30;
+
+        let config: ProfileOverridesConfig =
+            toml::from_str("[profile.nvidia-55]\nframebuffer = \"4GiB\"\n").unwrap();
+        let overrides = Overrides::from(config);
+        let target = Target {
+            vgpu_type_id: 55,
+            vgpu_name: String::new(),
+            vgpu_class: String::new(),
+            mdev: None,
+            gpu: None,
+        };
+
+        let apply = |action: Action| {
+            let mut config = params.clone();
+            let mut explanation = Explanation::default();
+            let result = apply_rules(&mut config, &overrides, &target, action, &mut explanation);
+
+            (result, config.fb_length, explanation.fields.len())
+        };
+
+        assert_eq!(apply(Action::Warn), (true, 4 << 30, 1));
+        assert_eq!(apply(Action::Reject), (true, 2 << 30, 0));
+        assert!(!apply(Action::Fail).0);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let mut params: NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 =
+            unsafe { mem::zeroed() };
+        params.vgpu_type = 55;
+        params.max_instance = 12;
+        params.num_heads = 1;
+        params.fb_length = 2 << 30;
+
+        let target = Target {
+            vgpu_type_id: 55,
+            vgpu_name: String::new(),
+            vgpu_class: String::new(),
+            mdev: None,
+            gpu: None,
+        };
+        let apply = |data: &str, action: Action| {
+            let config: ProfileOverridesConfig = toml::from_str(data).unwrap();
+            let overrides = Overrides::from(config);
+            let mut config = params.clone();
+            let result = apply_rules(
+                &mut config,
+                &overrides,
+                &target,
+                action,
+                &mut Explanation::default(),
+            );
+
+            (result, config.num_heads, config.fb_length)
+        };
+
+        let data = "[profile.nvidia-55]\nnum_displays = 2\n\
+                    [[rule]]\noverride = { framebuffer = \"4GiB\" }\ndry_run = true\nstop = true\n\
+                    [[rule]]\noverride = { num_displays = 4 }\n";
+        assert_eq!(apply(data, Action::Fail), (true, 4, 2 << 30));
+
+        let data = format!("dry_run = true\n{}", data);
+        assert_eq!(apply(&data, Action::Fail), (true, 1, 2 << 30));
+    }
+
+    #[test]
+    fn test_explanation() {
+        let path = temp_overrides("explain");
+
+        fs::write(
+            &path,
+            "[profile.nvidia-55]\nnum_displays = 2\nframebuffer = \"1GiB\"\n\
+             extra_params = { 3 = 1 }\n\
+             [mdev.00000000-0000-0000-0000-000000000100]\nnum_displays = 4\n",
+        )
+        .unwrap();
+
+        let overrides = load_overrides_from(&path).unwrap();
+        let mut params: NvA082CtrlCmdHostVgpuDeviceGetVgpuTypeInfoParamsV525 =
+            unsafe { mem::zeroed() };
+        params.vgpu_type = 55;
+        params.num_heads = 1;
+        params.fb_length = 2 << 30;
+
+        let mdev = Uuid(0, 0, 0, [0, 0, 0, 0, 0, 0, 0x01, 0x00]);
+        let target = Target {
+            vgpu_type_id: 55,
+            vgpu_name: String::new(),
+            vgpu_class: String::new(),
+            mdev: Some(mdev),
+            gpu: None,
+        };
+        let mut explanation = Explanation::new("nvidia-55".to_string(), Some(mdev));
+
+        assert!(apply_rules(
+            &mut params,
+            &overrides,
+            &target,
+            Action::Warn,
+            &mut explanation,
+        ));
+
+        let num_heads = &explanation.fields["num_heads"];
+        assert_eq!(num_heads.original, "1");
+        assert_eq!(num_heads.value, "4");
+        assert_eq!(
+            num_heads.rule,
+            "mdev UUID 00000000-0000-0000-0000-000000000100"
+        );
+        assert_eq!(num_heads.source, Some(Source::File(path.clone())));
+
+        let fb_length = &explanation.fields["fb_length"];
+        assert_eq!(fb_length.value, (1u64 << 30).to_string());
+        assert_eq!(fb_length.rule, "profile nvidia-55");
+
+        // Tables are attributed to where their keys were set.
+        let extra_params = &explanation.fields["vgpu_extra_params"];
+        assert_eq!(extra_params.source, Some(Source::File(path.clone())));
+        assert_eq!(explanation.fields.len(), 3);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_override_keys_version() {
+        let path = temp_overrides("version");
+
+        fs::write(
+            &path,
+            "version = 1\n[profile.nvidia-55]\ncard_name = \"GRID P40-2A\"\nvgpu_type = \"NVS\"\n\
+             mig_instance_size = 1\n[[rule]]\noverride = { card_name = \"GRID P40-1A\" }\n",
+        )
+        .unwrap();
+
+        let config = load_uncached(&path);
+        let profile = &config.profile["nvidia-55"];
+        assert_eq!(profile.card_name.as_deref(), Some("GRID P40-2A"));
+        assert_eq!(profile.vgpu_type.as_deref(), Some("NVS"));
+        assert_eq!(profile.mig_instance_size, Some(Expr::Number(1)));
+        assert_eq!(
+            config.rule[0].config_override.card_name.as_deref(),
+            Some("GRID P40-1A")
+        );
+
+        let findings = validate::check(&fs::read_to_string(&path).unwrap(), &SCHEMA);
+        assert!(findings.unknown.is_empty());
+        assert!(findings.deprecated.is_empty());
+
+        fs::write(&path, "version = 2\n[profile.nvidia-55]\n").unwrap();
+        assert!(layered::load(&path, &SCHEMA, Strictness::Lenient, StrictModes::Yes).is_err());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_override_keys() {
+        let unknown_keys = validate::check(
+            "[profile.nvidia-55]\ncuda_enable = 1\n[mdevs.x]\n\
+             [process.default.profile.nvidia-55]\nframebufer = 1\n\
+             [[rule]]\nmatch = { gpu_bfd = \"0000:41:00.0\" }\n\
+             [gpu.\"0000:41:00.0\"]\nframe_buffer = 1\n",
+            &SCHEMA,
+        )
+        .unknown;
+        let unknown_keys: Vec<_> = unknown_keys.iter().map(ToString::to_string).collect();
+
+        assert_eq!(
+            unknown_keys,
+            vec![
+                "line 2: unknown key `profile.nvidia-55.cuda_enable`, did you mean `cuda_enabled`?",
+                "line 3: unknown key `mdevs`, did you mean `mdev`?",
+                "line 5: unknown key `process.default.profile.nvidia-55.framebufer`, did you mean `framebuffer`?",
+                "line 7: unknown key `rule[0].match.gpu_bfd`, did you mean `gpu_bdf`?",
+                "line 9: unknown key `gpu.\"0000:41:00.0\".frame_buffer`, did you mean `framebuffer`?",
+            ]
+        );
+        assert!(validate::check(OVERRIDES, &SCHEMA).unknown.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_gpu_keys() {
+        let path = temp_overrides("invalid-gpu-keys");
+
+        fs::write(
+            &path,
+            "[gpu.\"0000:41:00.0\"]\ncuda_enabled = 1\n[gpu.\"41:00\"]\ncuda_enabled = 1\n",
+        )
+        .unwrap();
+
+        let invalid_keys: Vec<_> = validate::check(&fs::read_to_string(&path).unwrap(), &SCHEMA)
+            .invalid
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            invalid_keys,
+            vec!["line 3: invalid key `gpu.\"41:00\"`, expected a PCI address such as `0000:41:00.0`"]
+        );
+
+        assert!(layered::load(&path, &SCHEMA, Strictness::Strict, StrictModes::Yes).is_err());
+
+        let overrides = Overrides::from(load_uncached(&path));
+        assert_eq!(overrides.rules.len(), 1);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_gpu_vgpud_reported_keys() {
+        let path = temp_overrides("gpu-vgpud-reported-keys");
+
+        fs::write(
+            &path,
+            "[gpu.\"0000:41:00.0\"]\ncuda_enabled = 1\nframebuffer = \"2GiB\"\n\
+             [[rule]]\nmatch = { device_id = 0x1e30 }\noverride = { max_instances = 2 }\n",
+        )
+        .unwrap();
+
+        let invalid_keys: Vec<_> = validate::check(&fs::read_to_string(&path).unwrap(), &SCHEMA)
+            .invalid
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            invalid_keys,
+            vec![
+                "line 3: invalid key `gpu.\"0000:41:00.0\".framebuffer`, nvidia-vgpud does not \
+                 know the physical GPU and reports the profile without it"
+            ]
+        );
+
+        assert!(layered::load(&path, &SCHEMA, Strictness::Strict, StrictModes::Yes).is_err());
+
+        let overrides = Overrides::from(load_uncached(&path));
+        assert_eq!(overrides.rules.len(), 2);
+        assert!(overrides.rules[0]
+            .rule
+            .config_override
+            .cuda_enabled
+            .is_some());
+        assert!(overrides.rules[0]
+            .rule
+            .config_override
+            .framebuffer
+            .is_none());
+        assert!(overrides.rules[1]
+            .rule
+            .config_override
+            .max_instances
+            .is_none());
+
+        let mut config_override: VgpuProfileOverride = toml::from_str(
+            "card_name = \"x\"\nmax_instances = 1\nnum_displays = 1\ndisplay_width = 1\n\
+             display_height = 1\nresolution = \"4k\"\nfrl_config = 1\nframebuffer = 1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config_override.remove_vgpud_reported(),
+            schema::VGPUD_REPORTED_FIELDS
+        );
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(feature = "proxmox")]
+    #[test]
+    fn test_invalid_vm_keys() {
+        let path = temp_overrides("invalid-vm-keys");
+
+        fs::write(
+            &path,
+            "[vm.256]\ncuda_enabled = 1\n[vm.\"999-0\"]\n[process.default.vm.cad]\n",
+        )
+        .unwrap();
+
+        let invalid_keys: Vec<_> = validate::check(&fs::read_to_string(&path).unwrap(), &SCHEMA)
+            .invalid
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            invalid_keys,
+            vec![
+                "line 3: invalid key `vm.999-0`, expected a VMID or a range of VMIDs such as `1000-1999`",
+                "line 4: invalid key `process.default.vm.cad`, expected a VMID or a range of VMIDs such as `1000-1999`",
+            ]
+        );
+
+        assert!(layered::load(&path, &SCHEMA, Strictness::Strict, StrictModes::Yes).is_err());
+
+        let overrides = Overrides::from(load_uncached(&path));
+        assert_eq!(overrides.rules.len(), 1);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    /// Compares the per-call cost of reading and parsing the profile overrides on every call, as
+    /// done before the cache, with the cached lookup.
+    ///
+    /// Run with `cargo test --release -- --ignored --nocapture bench_load_overrides`.
+    #[test]
+    #[ignore]
+    fn bench_load_overrides() {
+        const ITERATIONS: u32 = 10_000;
+
+        let path = temp_overrides("bench");
+
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            load_uncached(&path);
+        }
+        let uncached = start.elapsed() / ITERATIONS;
+
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            load_overrides_from(&path).unwrap();
+        }
+        let cached = start.elapsed() / ITERATIONS;
+
+        println!("uncached: {:?}/call, cached: {:?}/call", uncached, cached);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
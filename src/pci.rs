@@ -0,0 +1,158 @@
+// SPDX-License-Identifier: MIT
+
+//! PCI addresses and IDs of physical GPUs as passed by the driver.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{Deserialize, Deserializer, Error, Unexpected, Visitor};
+
+/// PCI address packed as the driver passes it in `gpu_pci_bdf`:
+/// `domain << 16 | bus << 8 | device << 3 | function`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PciBdf(pub u32);
+
+impl PciBdf {
+    #[inline]
+    pub fn new(domain: u16, bus: u8, device: u8, function: u8) -> Self {
+        PciBdf(
+            (domain as u32) << 16
+                | (bus as u32) << 8
+                | ((device & 0x1f) as u32) << 3
+                | (function & 0x7) as u32,
+        )
+    }
+
+    #[inline]
+    pub fn domain(self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+
+    #[inline]
+    pub fn bus(self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    #[inline]
+    pub fn device(self) -> u8 {
+        ((self.0 >> 3) & 0x1f) as u8
+    }
+
+    #[inline]
+    pub fn function(self) -> u8 {
+        (self.0 & 0x7) as u8
+    }
+}
+
+impl fmt::Display for PciBdf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{:x}",
+            self.domain(),
+            self.bus(),
+            self.device(),
+            self.function()
+        )
+    }
+}
+
+impl FromStr for PciBdf {
+    type Err = ();
+
+    /// Parses `domain:bus:device.function` as shown by `lspci -D`, or `bus:device.function` for
+    /// domain 0.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, function) = s.rsplit_once('.').ok_or(())?;
+        let mut parts = address.rsplit(':');
+
+        let device = parts.next().ok_or(())?;
+        let bus = parts.next().ok_or(())?;
+        let domain = parts.next().unwrap_or("0");
+
+        if parts.next().is_some() {
+            return Err(());
+        }
+
+        let device = u8::from_str_radix(device, 16).map_err(|_| ())?;
+        let function = u8::from_str_radix(function, 16).map_err(|_| ())?;
+
+        if device > 0x1f || function > 0x7 {
+            return Err(());
+        }
+
+        Ok(PciBdf::new(
+            u16::from_str_radix(domain, 16).map_err(|_| ())?,
+            u8::from_str_radix(bus, 16).map_err(|_| ())?,
+            device,
+            function,
+        ))
+    }
+}
+
+impl<'de> Deserialize<'de> for PciBdf {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(PciBdfVisitor)
+    }
+}
+
+struct PciBdfVisitor;
+
+impl<'de> Visitor<'de> for PciBdfVisitor {
+    type Value = PciBdf;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("PCI address such as \"0000:41:00.0\"")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        v.trim()
+            .parse()
+            .map_err(|_| Error::invalid_value(Unexpected::Str(v), &self))
+    }
+}
+
+/// The physical GPU a vGPU is created on, as reported by `NV0000_CTRL_CMD_VGPU_GET_START_DATA`
+/// and `NV0000_CTRL_CMD_VGPU_CREATE_DEVICE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PhysicalGpu {
+    pub bdf: PciBdf,
+    /// PCI device ID in the upper 16 bits and vendor ID in the lower 16 bits, the same layout as
+    /// `pci_device_id` of `NV2080_CTRL_CMD_BUS_GET_PCI_INFO`.
+    pub pci_id: u32,
+}
+
+impl PhysicalGpu {
+    #[inline]
+    pub fn device_id(&self) -> u16 {
+        (self.pci_id >> 16) as u16
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PciBdf;
+
+    #[test]
+    fn test_pci_bdf() {
+        let bdf = PciBdf(0x0004_4101);
+
+        assert_eq!(bdf.domain(), 4);
+        assert_eq!(bdf.bus(), 0x41);
+        assert_eq!(bdf.device(), 0);
+        assert_eq!(bdf.function(), 1);
+        assert_eq!(bdf.to_string(), "0004:41:00.1");
+
+        assert_eq!("0004:41:00.1".parse(), Ok(bdf));
+        assert_eq!("41:1f.7".parse(), Ok(PciBdf::new(0, 0x41, 0x1f, 7)));
+        assert_eq!("0000:41:20.0".parse::<PciBdf>(), Err(()));
+        assert_eq!("0000:41:00".parse::<PciBdf>(), Err(()));
+        assert_eq!("0:0000:41:00.0".parse::<PciBdf>(), Err(()));
+    }
+}
@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: MIT
+
+//! Ownership and permission checks of configuration files, similar to `StrictModes` of sshd.
+//!
+//! The library runs as root inside the NVIDIA daemons, so anyone able to change the
+//! configuration can change what the kernel driver is told. A file is only trusted when it and
+//! every directory leading up to it are owned by root or the effective user, and are not writable
+//! by the group or others. Directories with the sticky bit set, such as `/tmp`, may be writable
+//! by others as they do not allow replacing entries owned by someone else.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::log::error;
+
+/// What to do with a file that fails the checks, selected with the `VGPU_UNLOCK_STRICT_MODES`
+/// environment variable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StrictModes {
+    /// Refuse to load the file.
+    Yes,
+    /// Log why the file is not trusted and load it anyway. This is the default for now, so that
+    /// existing installs with a group writable file keep starting after an upgrade.
+    Warn,
+    /// Skip the checks.
+    No,
+}
+
+impl StrictModes {
+    pub fn from_env() -> Self {
+        let value = match env::var("VGPU_UNLOCK_STRICT_MODES") {
+            Ok(value) => value,
+            Err(_) => return StrictModes::Warn,
+        };
+
+        match value.trim() {
+            "yes" => StrictModes::Yes,
+            "warn" => StrictModes::Warn,
+            "no" => StrictModes::No,
+            value => {
+                error!(
+                    "Unknown VGPU_UNLOCK_STRICT_MODES '{}', expected 'yes', 'warn' or 'no'",
+                    value
+                );
+
+                StrictModes::Warn
+            }
+        }
+    }
+}
+
+/// Why a file is not trusted.
+#[derive(Debug)]
+pub enum Insecure {
+    /// The path or one of its parents could not be inspected.
+    Metadata(PathBuf, io::Error),
+    /// The path or one of its parents is owned by someone other than root or the effective user.
+    Owner(PathBuf, u32),
+    /// The path or one of its parents is writable by the group or others.
+    Writable(PathBuf, u32),
+}
+
+impl fmt::Display for Insecure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Insecure::Metadata(path, e) => {
+                write!(f, "failed to check '{}': {}", path.display(), e)
+            }
+            Insecure::Owner(path, uid) => write!(
+                f,
+                "'{}' is owned by uid {} instead of root or the current user",
+                path.display(),
+                uid
+            ),
+            Insecure::Writable(path, mode) => write!(
+                f,
+                "'{}' is writable by the group or others (mode {:o})",
+                path.display(),
+                mode & 0o7777
+            ),
+        }
+    }
+}
+
+/// Checks `path` according to `strict_modes`. Failed checks are only logged with
+/// [`StrictModes::Warn`].
+pub fn enforce(path: &Path, strict_modes: StrictModes) -> Result<(), Insecure> {
+    if strict_modes == StrictModes::No {
+        return Ok(());
+    }
+
+    match check(path) {
+        Err(insecure) if strict_modes == StrictModes::Warn => {
+            error!(
+                "Not trusting '{}': {}, loading it anyway",
+                path.display(),
+                insecure
+            );
+
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Checks the ownership and mode of `path` and every directory leading up to it.
+pub fn check(path: &Path) -> Result<(), Insecure> {
+    let path = fs::canonicalize(path).map_err(|e| Insecure::Metadata(path.to_path_buf(), e))?;
+    let euid = unsafe { libc::geteuid() };
+
+    for ancestor in path.ancestors() {
+        let metadata =
+            fs::metadata(ancestor).map_err(|e| Insecure::Metadata(ancestor.to_path_buf(), e))?;
+        let uid = metadata.uid();
+        let mode = metadata.mode();
+
+        if uid != 0 && uid != euid {
+            return Err(Insecure::Owner(ancestor.to_path_buf(), uid));
+        }
+
+        let sticky_dir = metadata.is_dir() && mode & libc::S_ISVTX != 0;
+
+        if mode & 0o022 != 0 && !sticky_dir {
+            return Err(Insecure::Writable(ancestor.to_path_buf(), mode));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use std::process;
+
+    use super::{check, Insecure};
+
+    #[test]
+    fn test_check() {
+        let dir = env::temp_dir().join(format!("vgpu_unlock-test-permissions-{}", process::id()));
+        let path = dir.join("config.toml");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::set_permissions(&dir, Permissions::from_mode(0o755)).unwrap();
+        fs::write(&path, "").unwrap();
+
+        fs::set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+        assert!(check(&path).is_ok());
+
+        fs::set_permissions(&path, Permissions::from_mode(0o666)).unwrap();
+        assert!(
+            matches!(check(&path), Err(Insecure::Writable(p, _)) if p.ends_with("config.toml"))
+        );
+
+        fs::set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+        fs::set_permissions(&dir, Permissions::from_mode(0o775)).unwrap();
+        assert!(
+            matches!(check(&path), Err(Insecure::Writable(p, _)) if p == fs::canonicalize(&dir).unwrap())
+        );
+
+        assert!(matches!(
+            check(&dir.join("missing.toml")),
+            Err(Insecure::Metadata(..))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
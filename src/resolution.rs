@@ -0,0 +1,119 @@
+// SPDX-License-Identifier: MIT
+
+//! Display resolutions of vGPU profiles, written as `WIDTHxHEIGHT` or as a named preset.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{Deserialize, Deserializer, Error, Unexpected, Visitor};
+
+/// Named resolutions accepted in place of `WIDTHxHEIGHT`.
+static PRESETS: &[(&str, Resolution)] = &[
+    ("720p", Resolution::new(1280, 720)),
+    ("1080p", Resolution::new(1920, 1080)),
+    ("fhd", Resolution::new(1920, 1080)),
+    ("1440p", Resolution::new(2560, 1440)),
+    ("qhd", Resolution::new(2560, 1440)),
+    ("2160p", Resolution::new(3840, 2160)),
+    ("4k", Resolution::new(3840, 2160)),
+    ("uhd", Resolution::new(3840, 2160)),
+    ("5k", Resolution::new(5120, 2880)),
+    ("4320p", Resolution::new(7680, 4320)),
+    ("8k", Resolution::new(7680, 4320)),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Resolution {
+    #[inline]
+    pub const fn new(width: u32, height: u32) -> Self {
+        Resolution { width, height }
+    }
+
+    /// Number of pixels of a single display at this resolution.
+    #[inline]
+    pub fn pixels(self) -> u64 {
+        u64::from(self.width) * u64::from(self.height)
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = ();
+
+    /// Parses `WIDTHxHEIGHT`, e.g. `3840x2160`, or a preset such as `4k` or `1440p`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if let Some((_, resolution)) = PRESETS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        {
+            return Ok(*resolution);
+        }
+
+        let (width, height) = s.split_once(['x', 'X']).ok_or(())?;
+        let width = width.trim().parse().map_err(|_| ())?;
+        let height = height.trim().parse().map_err(|_| ())?;
+
+        if width == 0 || height == 0 {
+            return Err(());
+        }
+
+        Ok(Resolution::new(width, height))
+    }
+}
+
+impl<'de> Deserialize<'de> for Resolution {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(ResolutionVisitor)
+    }
+}
+
+struct ResolutionVisitor;
+
+impl<'de> Visitor<'de> for ResolutionVisitor {
+    type Value = Resolution;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("resolution such as \"3840x2160\" or \"4k\"")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        v.parse()
+            .map_err(|_| Error::invalid_value(Unexpected::Str(v), &self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Resolution;
+
+    #[test]
+    fn test_resolution() {
+        assert_eq!("3840x2160".parse(), Ok(Resolution::new(3840, 2160)));
+        assert_eq!(" 1920 X 1080 ".parse(), Ok(Resolution::new(1920, 1080)));
+        assert_eq!("4K".parse(), Ok(Resolution::new(3840, 2160)));
+        assert_eq!("1440p".parse(), Ok(Resolution::new(2560, 1440)));
+        assert_eq!("1920x".parse::<Resolution>(), Err(()));
+        assert_eq!("0x1080".parse::<Resolution>(), Err(()));
+        assert_eq!("3k".parse::<Resolution>(), Err(()));
+        assert_eq!(Resolution::new(3840, 2160).to_string(), "3840x2160");
+        assert_eq!(Resolution::new(3840, 2160).pixels(), 8_294_400);
+    }
+}
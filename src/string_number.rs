@@ -2,6 +2,7 @@ use std::borrow::Cow;
 use std::cmp::{Eq, PartialEq};
 use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 
 use serde::de::{Deserializer, Error};
 use serde::Deserialize;
@@ -16,28 +17,29 @@ impl<'de> Deserialize<'de> for U32 {
     {
         match NumberString::deserialize(deserializer)? {
             NumberString::Number(n) => Ok(Self(n)),
-            NumberString::String(s) => {
-                let s = s.trim();
+            NumberString::String(s) => s.parse().map_err(D::Error::custom),
+        }
+    }
+}
 
-                // Try to maintain compatibility with older Rust versions
-                let (v, radix) = match (s.get(0..2), s.get(2..)) {
-                    (Some(prefix), Some(suffix)) if prefix.eq_ignore_ascii_case("0b") => {
-                        (suffix, 2)
-                    }
-                    (Some(prefix), Some(suffix)) if prefix.eq_ignore_ascii_case("0x") => {
-                        (suffix, 16)
-                    }
-                    (_, _) => (s, 10),
-                };
+impl FromStr for U32 {
+    type Err = String;
 
-                match u32::from_str_radix(v, radix) {
-                    Ok(n) => Ok(Self(n)),
-                    Err(e) => Err(D::Error::custom(format!(
-                        "Failed to parse string as base-{} integer: {}",
-                        radix, e
-                    ))),
-                }
-            }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        let (v, radix) = match (s.get(0..2), s.get(2..)) {
+            (Some(prefix), Some(suffix)) if prefix.eq_ignore_ascii_case("0b") => (suffix, 2),
+            (Some(prefix), Some(suffix)) if prefix.eq_ignore_ascii_case("0x") => (suffix, 16),
+            (_, _) => (s, 10),
+        };
+
+        match u32::from_str_radix(v, radix) {
+            Ok(n) => Ok(Self(n)),
+            Err(e) => Err(format!(
+                "Failed to parse string as base-{} integer: {}",
+                radix, e
+            )),
         }
     }
 }
@@ -81,11 +83,6 @@ impl PartialEq<u32> for U32 {
     fn eq(&self, other: &u32) -> bool {
         PartialEq::eq(&self.0, other)
     }
-
-    #[inline]
-    fn ne(&self, other: &u32) -> bool {
-        PartialEq::ne(&self.0, other)
-    }
 }
 
 impl PartialEq<U32> for U32 {
@@ -93,9 +90,4 @@ impl PartialEq<U32> for U32 {
     fn eq(&self, other: &Self) -> bool {
         PartialEq::eq(&self.0, &other.0)
     }
-
-    #[inline]
-    fn ne(&self, other: &Self) -> bool {
-        PartialEq::ne(&self.0, &other.0)
-    }
 }
@@ -1,5 +1,9 @@
 use std::borrow::Cow;
 use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
 
 #[cfg(feature = "proxmox")]
 use crate::uuid::Uuid;
@@ -22,12 +26,94 @@ impl fmt::LowerHex for AlignedU64 {
     }
 }
 
+/// Returns the names the current process is known by, the name from `/proc/self/comm`
+/// followed by the file name of `/proc/self/exe` if that differs.
+///
+/// The name in `/proc/self/comm` is truncated to 15 characters, which is still enough for
+/// `nvidia-vgpu-mgr`.
+pub fn process_names() -> Vec<String> {
+    let mut names = Vec::new();
+
+    if let Ok(comm) = fs::read_to_string("/proc/self/comm") {
+        names.push(comm.trim_end_matches('\n').to_string());
+    }
+
+    if let Some(name) = fs::read_link("/proc/self/exe")
+        .ok()
+        .and_then(|exe| Some(exe.file_name()?.to_str()?.to_string()))
+    {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    names
+}
+
+/// Replaces the file at `path` with `data`, creating its directory if needed.
+///
+/// The data is written to a temporary file next to it first, so a partially written file is
+/// never read. The temporary file is named after the process, as both daemons may write the same
+/// file at once.
+pub fn write_atomic(path: &Path, data: &str) -> io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let result = fs::write(&temp_path, data).and_then(|_| fs::rename(&temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
 pub fn from_c_str(value: &[u8]) -> Cow<'_, str> {
     let len = value.iter().position(|&c| c == 0).unwrap_or(value.len());
 
     String::from_utf8_lossy(&value[..len])
 }
 
+/// Matches `text` against the glob `pattern`, where `*` matches any run of characters and `?`
+/// matches a single character. Every other character matches itself.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` in the pattern and the text position it was tried at.
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` match one more character.
+                Some((star, star_t)) => {
+                    backtrack = Some((star, star_t + 1));
+                    p = star + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 /// Extracts the VMID from the last segment of a mdev uuid
 ///
 /// For example, for this uuid 00000000-0000-0000-0000-000000000100
@@ -53,3 +139,22 @@ pub fn uuid_to_vmid(uuid: Uuid) -> Option<u64> {
     // Parse it as a normal decimal number to get the right vm id
     s.parse().ok()
 }
+
+#[cfg(test)]
+mod test {
+    use super::glob_match;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("GRID RTX6000-*Q", "GRID RTX6000-4Q"));
+        assert!(glob_match("GRID RTX6000-*Q", "GRID RTX6000-24Q"));
+        assert!(!glob_match("GRID RTX6000-*Q", "GRID RTX6000-4A"));
+        assert!(glob_match("GRID P40-?A", "GRID P40-2A"));
+        assert!(!glob_match("GRID P40-?A", "GRID P40-12A"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYbZ"));
+        assert!(glob_match("Quadro", "Quadro"));
+        assert!(!glob_match("Quadro", "Quadro2"));
+    }
+}
@@ -0,0 +1,484 @@
+// SPDX-License-Identifier: MIT
+
+//! Detection of unknown keys in configuration files.
+//!
+//! Unknown keys are otherwise ignored by serde, so a typo such as `cuda_enable` instead of
+//! `cuda_enabled` would silently do nothing. Each file is checked against a [`Schema`] before it
+//! is merged, and every unknown key is reported with its line number and the closest known key.
+//! Keys that were renamed since the version the file declares are reported as deprecated, and
+//! keys of tables such as `[gpu]` and `[vm]` that do not have the format of the table are
+//! reported as invalid.
+
+use std::cmp;
+use std::env;
+use std::fmt;
+
+#[cfg(test)]
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+#[cfg(test)]
+use serde::forward_to_deserialize_any;
+use toml_edit::{ImDocument, Item, TableLike};
+
+use crate::layered;
+use crate::log::error;
+use crate::migrate;
+use crate::overrides::vgpu_type_id;
+#[cfg(feature = "proxmox")]
+use crate::overrides::VmidRange;
+use crate::pci::PciBdf;
+use crate::schema::{Keys, Schema};
+use crate::string_number::U32;
+
+/// Whether unknown keys are an error, selected with the `VGPU_UNLOCK_CONFIG_VALIDATION`
+/// environment variable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strictness {
+    /// Unknown keys are logged and otherwise ignored. This is the default.
+    Lenient,
+    /// Unknown keys cause the file to be rejected.
+    Strict,
+}
+
+impl Strictness {
+    pub fn from_env() -> Self {
+        let value = match env::var("VGPU_UNLOCK_CONFIG_VALIDATION") {
+            Ok(value) => value,
+            Err(_) => return Strictness::Lenient,
+        };
+
+        match value.trim() {
+            "lenient" => Strictness::Lenient,
+            "strict" => Strictness::Strict,
+            value => {
+                error!(
+                    "Unknown VGPU_UNLOCK_CONFIG_VALIDATION '{}', expected 'strict' or 'lenient'",
+                    value
+                );
+
+                Strictness::Lenient
+            }
+        }
+    }
+}
+
+/// An unknown key found in a configuration file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownKey {
+    pub line: usize,
+    pub path: String,
+    pub suggestion: Option<&'static str>,
+}
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: unknown key `{}`", self.line, self.path)?;
+
+        if let Some(suggestion) = self.suggestion {
+            write!(f, ", did you mean `{}`?", suggestion)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A key that was renamed in a later version of the format than the file declares.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DeprecatedKey {
+    pub line: usize,
+    pub path: String,
+    pub replacement: &'static str,
+}
+
+impl fmt::Display for DeprecatedKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}: key `{}` is deprecated, use `{}` instead",
+            self.line, self.path, self.replacement
+        )
+    }
+}
+
+/// A key of a [`Schema::Keyed`] table that does not have the format of the table, or a key of a
+/// [`Schema::Restricted`] table that is not allowed there.
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidKey {
+    pub line: usize,
+    pub path: String,
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}: invalid key `{}`, {}",
+            self.line, self.path, self.reason
+        )
+    }
+}
+
+/// The keys of a document that are not allowed as they are by a schema.
+#[derive(Debug, Default)]
+pub struct Findings {
+    pub unknown: Vec<UnknownKey>,
+    pub deprecated: Vec<DeprecatedKey>,
+    pub invalid: Vec<InvalidKey>,
+}
+
+/// Returns every key in the TOML document `data` that is unknown to `schema`, deprecated by the
+/// version the document declares, or invalid for its table. The top-level `version` key is always
+/// allowed.
+///
+/// A document that fails to parse has no findings, the parse error is reported when the document
+/// is decoded.
+pub fn check(data: &str, schema: &Schema) -> Findings {
+    let document = match ImDocument::parse(data) {
+        Ok(document) => document,
+        Err(_) => return Findings::default(),
+    };
+    let version = document
+        .get(migrate::VERSION_KEY)
+        .and_then(Item::as_integer)
+        .unwrap_or(1);
+    let mut checker = Checker {
+        data,
+        version,
+        findings: Findings::default(),
+    };
+
+    for (name, item) in document.iter() {
+        if name != migrate::VERSION_KEY {
+            checker.check_key(document.as_table(), name, name, item, schema, "");
+        }
+    }
+
+    checker.findings
+}
+
+struct Checker<'a> {
+    data: &'a str,
+    version: i64,
+    findings: Findings,
+}
+
+impl Checker<'_> {
+    fn check_item(&mut self, item: &Item, schema: &Schema, path: &str) {
+        match schema {
+            Schema::Array(schema) => {
+                if let Some(array) = item.as_array_of_tables() {
+                    for (i, table) in array.iter().enumerate() {
+                        self.check_table(table, schema, &format!("{}[{}]", path, i));
+                    }
+                } else if let Some(array) = item.as_array() {
+                    for (i, value) in array.iter().enumerate() {
+                        if let Some(table) = value.as_inline_table() {
+                            self.check_table(table, schema, &format!("{}[{}]", path, i));
+                        }
+                    }
+                }
+            }
+            schema => {
+                if let Some(table) = item.as_table_like() {
+                    self.check_table(table, schema, path);
+                }
+            }
+        }
+    }
+
+    fn check_table(&mut self, table: &dyn TableLike, schema: &Schema, path: &str) {
+        for (name, item) in table.iter() {
+            self.check_key(table, name, name, item, schema, path);
+        }
+    }
+
+    /// Checks the key `name` of `table` as the key `field` of `schema`, which differ for renamed
+    /// keys.
+    fn check_key(
+        &mut self,
+        table: &dyn TableLike,
+        name: &str,
+        field: &str,
+        item: &Item,
+        schema: &Schema,
+        path: &str,
+    ) {
+        let key_path = layered::key_path(path, name);
+
+        match schema {
+            Schema::Struct(fields, nested) => {
+                if let Some((_, schema)) = nested.iter().find(|(nested, _)| *nested == field) {
+                    self.check_item(item, schema, &key_path);
+                } else if !fields.contains(&field) {
+                    let known = fields.iter().chain(nested.iter().map(|(field, _)| field));
+
+                    self.findings.unknown.push(UnknownKey {
+                        line: self.line(table, name),
+                        path: key_path,
+                        suggestion: closest(field, known),
+                    });
+                }
+            }
+            Schema::Map(schema) => self.check_item(item, schema, &key_path),
+            Schema::Keyed(keys, schema) => {
+                if !is_valid_key(*keys, name) {
+                    self.findings.invalid.push(InvalidKey {
+                        line: self.line(table, name),
+                        path: key_path.clone(),
+                        reason: format!("expected {}", keys.expected()),
+                    });
+                }
+
+                self.check_item(item, schema, &key_path);
+            }
+            // Arrays only hold values, not keys.
+            Schema::Array(_) => {}
+            Schema::Restricted(keys, reason, schema) => {
+                if keys.contains(&field) {
+                    self.findings.invalid.push(InvalidKey {
+                        line: self.line(table, name),
+                        path: key_path,
+                        reason: reason.to_string(),
+                    });
+                } else {
+                    self.check_key(table, name, field, item, schema, path);
+                }
+            }
+            Schema::Renamed(renames, schema) => {
+                match renames.iter().find(|rename| rename.from == field) {
+                    Some(rename) if rename.applies_to(self.version) => {
+                        self.findings.deprecated.push(DeprecatedKey {
+                            line: self.line(table, name),
+                            path: key_path,
+                            replacement: rename.to,
+                        });
+                        self.check_key(table, name, rename.to, item, schema, path);
+                    }
+                    Some(rename) => self.findings.unknown.push(UnknownKey {
+                        line: self.line(table, name),
+                        path: key_path,
+                        suggestion: Some(rename.to),
+                    }),
+                    None => self.check_key(table, name, field, item, schema, path),
+                }
+            }
+        }
+    }
+
+    fn line(&self, table: &dyn TableLike, name: &str) -> usize {
+        let span = table.get_key_value(name).and_then(|(key, _)| key.span());
+
+        span.map_or(0, |span| line_number(self.data, span.start))
+    }
+}
+
+/// Returns whether `key` has the format `keys`.
+pub fn is_valid_key(keys: Keys, key: &str) -> bool {
+    match keys {
+        Keys::VgpuType => vgpu_type_id(key).is_some(),
+        Keys::DeviceId => key.parse::<U32>().is_ok(),
+        Keys::PciAddress => key.parse::<PciBdf>().is_ok(),
+        #[cfg(feature = "proxmox")]
+        Keys::Vmids => key.parse::<VmidRange>().is_ok(),
+        // The `[vm]` table is not read without Proxmox support.
+        #[cfg(not(feature = "proxmox"))]
+        Keys::Vmids => true,
+    }
+}
+
+/// Returns the form of `key` that every way of writing it is merged under.
+pub fn normal_key(keys: Keys, key: &str) -> String {
+    match keys {
+        Keys::DeviceId => key.to_ascii_lowercase(),
+        Keys::VgpuType | Keys::PciAddress | Keys::Vmids => key.to_string(),
+    }
+}
+
+fn line_number(data: &str, offset: usize) -> usize {
+    data.as_bytes()[..cmp::min(offset, data.len())]
+        .iter()
+        .filter(|&&b| b == b'\n')
+        .count()
+        + 1
+}
+
+/// Returns the known name closest to `name`, if any is close enough to be a likely typo.
+fn closest<'a, I>(name: &str, known: I) -> Option<&'static str>
+where
+    I: Iterator<Item = &'a &'static str>,
+{
+    let max_distance = cmp::max(2, name.len() / 3);
+
+    known
+        .map(|&known| (edit_distance(name, known), known))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, known)| known)
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous + (ca != cb) as usize;
+
+            previous = row[j + 1];
+            row[j + 1] = cmp::min(substitution, cmp::min(row[j], row[j + 1]) + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Returns the names of the fields serde expects when deserializing the struct `T`, to check the
+/// field lists of [`crate::schema`].
+#[cfg(test)]
+pub fn fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields = None;
+
+    let _ = T::deserialize(FieldsDeserializer(&mut fields));
+
+    fields.unwrap_or(&[])
+}
+
+/// Deserializer that records the field names passed to `deserialize_struct` and fails.
+#[cfg(test)]
+struct FieldsDeserializer<'a>(&'a mut Option<&'static [&'static str]>);
+
+#[cfg(test)]
+impl<'de, 'a> Deserializer<'de> for FieldsDeserializer<'a> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        *self.0 = Some(fields);
+
+        Err(de::Error::custom("only the fields are recorded"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+
+    use super::{check, edit_distance, fields, InvalidKey, Keys, Schema, UnknownKey};
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Entry {
+        cuda_enabled: Option<u32>,
+        max_pixels: Option<u32>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Root {
+        unlock: Option<bool>,
+        profile: Option<toml::Table>,
+        gpu: Option<toml::Table>,
+    }
+
+    static ENTRY: Schema = Schema::Struct(&["cuda_enabled", "max_pixels"], &[]);
+    static ROOT: Schema = Schema::Struct(
+        &["unlock", "profile", "gpu"],
+        &[
+            ("profile", Schema::Map(&ENTRY)),
+            ("gpu", Schema::Keyed(Keys::PciAddress, &ENTRY)),
+        ],
+    );
+
+    #[test]
+    fn test_fields() {
+        assert_eq!(fields::<Entry>(), ["cuda_enabled", "max_pixels"]);
+        assert_eq!(fields::<Root>(), ["unlock", "profile", "gpu"]);
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("cuda_enable", "cuda_enabled"), 1);
+        assert_eq!(edit_distance("unlcok", "unlock"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", "abc"), 0);
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let data = "unlock = true\n\
+                    unlock_migraton = true\n\
+                    [profile.nvidia-55]\n\
+                    cuda_enable = 1\n\
+                    max_pixels = 1\n\
+                    [profile.nvidia-56]\n\
+                    completely_different = 1\n";
+
+        assert_eq!(
+            check(data, &ROOT).unknown,
+            vec![
+                UnknownKey {
+                    line: 2,
+                    path: "unlock_migraton".to_string(),
+                    suggestion: None,
+                },
+                UnknownKey {
+                    line: 4,
+                    path: "profile.nvidia-55.cuda_enable".to_string(),
+                    suggestion: Some("cuda_enabled"),
+                },
+                UnknownKey {
+                    line: 7,
+                    path: "profile.nvidia-56.completely_different".to_string(),
+                    suggestion: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let data = "[gpu.\"0000:41:00.0\"]\n\
+                    cuda_enabled = 1\n\
+                    [gpu.\"41:00\"]\n\
+                    cuda_enable = 1\n";
+        let findings = check(data, &ROOT);
+
+        assert_eq!(
+            findings.invalid,
+            vec![InvalidKey {
+                line: 3,
+                path: "gpu.\"41:00\"".to_string(),
+                reason: "expected a PCI address such as `0000:41:00.0`".to_string(),
+            }]
+        );
+        assert_eq!(findings.unknown.len(), 1);
+        assert_eq!(
+            findings.invalid[0].to_string(),
+            "line 3: invalid key `gpu.\"41:00\"`, expected a PCI address such as `0000:41:00.0`"
+        );
+    }
+}